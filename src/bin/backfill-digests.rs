@@ -0,0 +1,27 @@
+use anyhow::Result;
+
+use backer::config;
+use backer::db;
+use backer::hashing;
+use backer::interlude::*;
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+/// Adds digests by the algorithms configured in `digests` to files cataloged without them. SHA-1
+/// hashes stay the identity of files, the new digests are used to verify them.
+fn run() -> Result<()> {
+    let db = db::open("backer.db")?;
+    let config = config::read("backer.toml")?;
+    if config.digests.is_empty() {
+        iprintln!("No digests configured besides SHA-1, see `digests` in backer.toml");
+    }
+    for algorithm in config.digests {
+        let backfilled = hashing::backfill(&db, algorithm)?;
+        iprintln!(algorithm;? ": " backfilled;?);
+    }
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use anyhow::{bail, Result};
+
+use backer::db;
+use backer::interlude::*;
+
+const USAGE: &str = "usage: cameras [--lenses | --focal-lengths]";
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+fn run() -> Result<()> {
+    let db = db::open("backer.db")?;
+    let db = db.lock().unwrap();
+    match std::env::args().nth(1).as_deref() {
+        None => {
+            for row in db::cameras(&db).run(()) {
+                let (make, model, count) = row?;
+                let camera = [make, model].into_iter().flatten().collect::<Vec<_>>();
+                iprintln!(camera.join(" ") ": " count);
+            }
+        }
+        Some("--lenses") => {
+            for row in db::lenses(&db).run(()) {
+                let (lens, count) = row?;
+                iprintln!(lens ": " count);
+            }
+        }
+        Some("--focal-lengths") => {
+            for row in db::focal_lengths(&db).run(()) {
+                let (mm, count) = row?;
+                iprintln!(mm "mm: " count);
+            }
+        }
+        Some(_) => bail!(USAGE),
+    }
+    Ok(())
+}
@@ -3,13 +3,40 @@ use std::collections::HashMap;
 use regex::Regex;
 
 use backer::config::{self, *};
+use backer::imaging::DateTag;
 use backer::interlude::*;
+use backer::model::HashAlgorithm;
 
 fn main() {
     iprintln!("SAMPLE:\n" toml::to_string(&Config {
         markers: Markers{
             disk: Vec::new(),
+            host: HashMap::new(),
+            os: HashMap::new(),
+            discover: Some(Discover {
+                roots: vec!["/media".into(), "/run/media".into()],
+                depth: 3,
+            }),
         },
+        ignore_small: Some(IgnoreSmall {
+            w: Some(1024),
+            h: Some(1024),
+            bytes: None,
+        }),
+        exclude: vec!["**/@eaDir".to_string()],
+        symlinks: None,
+        file_date: Some(FileDate::Modified),
+        date_tags: Some(DateTag::DEFAULT_PRIORITY.to_vec()),
+        timezone: None,
+        geocoding: Some(Geocoding {
+            cities: "geonames/cities1000.txt".into(),
+            admin1: Some("geonames/admin1CodesASCII.txt".into()),
+            max_km: 50.0,
+        }),
+        defer_thumbnails: false,
+        max_in_flight_mb: Some(512),
+        digests: vec![HashAlgorithm::Sha256],
+        marker: HashMap::new(),
         date_path: HashMap::from([
             ("marker-x".to_string(), vec![
                 DatePath {
@@ -16,10 +16,10 @@ const YMD_HMS: &str = "%Y-%m-%d %H:%M:%S";
 const YMD: &str = "%Y-%m-%d";
 
 fn run() -> Result<()> {
-    let mut config = config::read("backer.toml")?;
-    for marker_path in config.markers.disk {
+    let config = config::read("backer.toml")?;
+    for marker_path in &config.markers.for_current_host().disk {
         iprintln!("MARKER: " marker_path;?);
-        let tree = match Tree::open(marker_path, &config.date_path) {
+        let tree = match Tree::open(marker_path, &config) {
             Ok(t) => t,
             Err(e) => {
                 ieprintln!("Skipping: " e);
@@ -27,7 +27,6 @@ fn run() -> Result<()> {
             }
         };
 
-        let date_paths = config.date_path.remove(&tree.marker);
         'files: for entry in tree.iter() {
             let entry = match entry {
                 Ok(entry) => entry,
@@ -44,7 +43,7 @@ fn run() -> Result<()> {
                     entry.relative_path()
                 );
             };
-            for date_path in date_paths.iter().flatten() {
+            for date_path in &tree.date_paths {
                 if let Some(found) = date_path.path.captures(&relative) {
                     let mut buf = String::new();
                     found.expand(&date_path.date, &mut buf);
@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+use backer::db;
+use backer::interlude::*;
+use backer::marker::{self, Backend, Marker};
+
+const USAGE: &str = "usage: marker init DIR --id ID [--label LABEL] [--notes NOTES] \
+                     [--backend disk|ipfs] [--read-only]
+       marker confirm DIR
+       marker reid DIR NEW_ID [--clone]";
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+fn run() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("init") => init(args),
+        Some("confirm") => confirm(args),
+        Some("reid") => reid(args),
+        _ => bail!(USAGE),
+    }
+}
+
+fn init(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut dir = None;
+    let mut id = None;
+    let mut label = None;
+    let mut notes = None;
+    let mut backend = Backend::default();
+    let mut read_only = false;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| ifmt!("missing value of " arg "; " USAGE))
+        };
+        match arg.as_str() {
+            "--id" => id = Some(value()?),
+            "--label" => label = Some(value()?),
+            "--notes" => notes = Some(value()?),
+            "--backend" => {
+                backend = serde_json::from_value(serde_json::Value::String(value()?))
+                    .context("unknown backend")?
+            }
+            "--read-only" => read_only = true,
+            _ if dir.is_none() && !arg.starts_with("--") => dir = Some(PathBuf::from(arg)),
+            _ => bail!(ifmt!("unexpected argument " arg;? "; " USAGE)),
+        }
+    }
+    let (Some(dir), Some(id)) = (dir, id) else {
+        bail!(USAGE);
+    };
+
+    let marker = Marker {
+        label,
+        notes,
+        backend,
+        read_only,
+        ..Marker::new(id)
+    };
+    let db = db::open("backer.db")?;
+    let path = marker::init(&dir, &marker, &db.lock().unwrap())?;
+    iprintln!("Created marker file: " path;?);
+    Ok(())
+}
+
+/// Accepts the tree at DIR as the one identified by its marker id, e.g. after the marker file was
+/// deliberately recreated.
+fn confirm(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let (Some(dir), None) = (args.next(), args.next()) else {
+        bail!(USAGE);
+    };
+    let path = PathBuf::from(dir).join(marker::FILE_NAME);
+    let (root, marker) = marker::read(&path)?;
+    let db = db::open("backer.db")?;
+    marker::confirm(&db.lock().unwrap(), &path, &marker)?;
+    iprintln!("Confirmed marker " marker.id;? " at: " root;?);
+    Ok(())
+}
+
+/// Gives the tree at DIR a new marker id. With `--clone`, the tree is a copy of another one, which
+/// keeps the old id, so cataloged locations are copied to the new id instead of moved.
+fn reid(args: impl Iterator<Item = String>) -> Result<()> {
+    let mut clone = false;
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--clone" => clone = true,
+            _ if !arg.starts_with("--") => positional.push(arg),
+            _ => bail!(ifmt!("unexpected argument " arg;? "; " USAGE)),
+        }
+    }
+    let [dir, new_id] = <[String; 2]>::try_from(positional).map_err(|_| anyhow!(USAGE))?;
+
+    let path = PathBuf::from(dir).join(marker::FILE_NAME);
+    let db = db::open("backer.db")?;
+    let marker = marker::reid(&db.lock().unwrap(), &path, &new_id, clone)?;
+    iprintln!("Marker at " path;? " now has id: " marker.id;?);
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use anyhow::{bail, Result};
+
+use backer::config;
+use backer::db;
+use backer::geocoding::Places;
+use backer::interlude::*;
+use backer::model::GpsPosition;
+
+const USAGE: &str = "usage: places [--update]";
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+fn run() -> Result<()> {
+    let db = db::open("backer.db")?;
+    let db = db.lock().unwrap();
+    match std::env::args().nth(1).as_deref() {
+        None => {}
+        // Resolve names for all files, e.g. after geocoding got enabled or its dataset updated.
+        Some("--update") => {
+            let config = config::read("backer.toml")?;
+            let Some(geocoding) = &config.geocoding else {
+                bail!("no [geocoding] section in config");
+            };
+            let places = Places::load(geocoding)?;
+            let files = db::geotagged_files(&db)
+                .run(())
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for (hash, lat, lon) in &files {
+                let gps = GpsPosition {
+                    lat: *lat,
+                    lon: *lon,
+                    alt: None,
+                };
+                db::set_place(&db, hash, places.nearest(&gps))?;
+            }
+            iprintln!("Updated places of " files.len() " files.\n");
+        }
+        Some(_) => bail!(USAGE),
+    }
+
+    let mut query = db::places(&db);
+    for row in query.run(()) {
+        let (country, region, city, count) = row?;
+        let place = [Some(country), region, Some(city)]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(", ");
+        iprintln!(place ": " count);
+    }
+    Ok(())
+}
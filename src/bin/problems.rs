@@ -0,0 +1,21 @@
+use anyhow::Result;
+
+use backer::db;
+use backer::interlude::*;
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+fn run() -> Result<()> {
+    let db = db::open("backer.db")?;
+    let db = db.lock().unwrap();
+    let mut query = db::scan_problems(&db);
+    for row in query.run(()) {
+        let (marker, path, kind, message) = row?;
+        iprintln!(marker ": " path " [" kind;? "] " message);
+    }
+    Ok(())
+}
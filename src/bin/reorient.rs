@@ -0,0 +1,18 @@
+use anyhow::Result;
+
+use backer::db;
+use backer::interlude::*;
+use backer::scanning::reorient_thumbnails;
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+/// Corrects orientation of thumbnails in catalogs made before thumbnails were deoriented, for
+/// files in trees which are mounted now.
+fn run() -> Result<()> {
+    let db = db::open("backer.db")?;
+    reorient_thumbnails(&db)
+}
@@ -0,0 +1,62 @@
+use anyhow::{bail, Context, Result};
+
+use backer::config;
+use backer::db;
+use backer::interlude::*;
+use backer::scanning::Tree;
+use backer::scrub::{scrub, ScrubOptions};
+
+const USAGE: &str = "usage: scrub [--percent N] [--mb-per-sec N] MARKER_PATH";
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+/// Verifies files of the tree with the marker, e.g. the least recently verified tenth of it each
+/// night with `--percent 10`. An interrupted scrub is resumed when run again.
+fn run() -> Result<()> {
+    let mut options = ScrubOptions::default();
+    let mut marker_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                iprintln!(USAGE);
+                return Ok(());
+            }
+            "--percent" => {
+                let percent = args.next().context(USAGE)?;
+                options.percent = percent.parse().context("invalid --percent")?;
+            }
+            "--mb-per-sec" => {
+                let mb: u64 = args
+                    .next()
+                    .context(USAGE)?
+                    .parse()
+                    .context("invalid --mb-per-sec")?;
+                options.bytes_per_sec = Some(mb * 1024 * 1024);
+            }
+            _ if marker_path.is_none() => marker_path = Some(arg),
+            _ => bail!(USAGE),
+        }
+    }
+    let Some(marker_path) = marker_path else {
+        bail!(USAGE);
+    };
+
+    let db = db::open("backer.db")?;
+    let config = config::read("backer.toml")?;
+    let tree = Tree::open(&marker_path, &config)?;
+    let scrubbed = scrub(&db, &tree, options)?;
+    iprintln!("\n" scrubbed;?);
+
+    let db = db.lock().unwrap();
+    let mut query = db::scrub_mismatches(&db);
+    for row in query.run(()) {
+        let (marker, path, algorithm, expected, actual) = row?;
+        iprintln!("MISMATCH " marker ": " path " [" algorithm;? "] " actual " != " expected);
+    }
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use anyhow::Result;
+
+use backer::db;
+use backer::interlude::*;
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+fn run() -> Result<()> {
+    let db = db::open("backer.db")?;
+    let db = db.lock().unwrap();
+    let mut query = db::skipped(&db);
+    for row in query.run(()) {
+        let (marker, path, reason) = row?;
+        iprintln!(marker ": " path " (" reason ")");
+    }
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use backer::config;
+use backer::config::Config;
 use backer::db;
 use backer::interlude::*;
 use backer::scanning::*;
@@ -16,7 +16,7 @@ fn run() -> Result<()> {
     // let config = config::read("backer.toml")?;
 
     let marker_path = r"c:\fotki\backer-id.json";
-    let tree = Tree::open(marker_path, &config::DatePathsPerMarker::default())?;
+    let tree = Tree::open(marker_path, &Config::default())?;
 
     stage2(&tree, &db)?;
 
@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+
+use backer::imaging::{self, THUMBNAIL_SIZE};
+use backer::interlude::*;
+
+const USAGE: &str = "usage: thumbnail-bench [DIR]";
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+/// Compares making thumbnails by fully decoding and resizing JPEGs, with the scaled decoding used
+/// by scanning. Photos are taken from DIR, or generated at typical camera sizes if not given.
+fn run() -> Result<()> {
+    let fixtures = match std::env::args().nth(1).as_deref() {
+        None => generated_fixtures()?,
+        Some("-h" | "--help") => {
+            iprintln!(USAGE);
+            return Ok(());
+        }
+        Some(dir) => fixtures_from(dir.into())?,
+    };
+    iprintln!("Fixtures: " fixtures.len() " JPEGs");
+
+    let full = measure(&fixtures, |buf| {
+        let img = image::load_from_memory(buf)?;
+        Ok(img.resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::CatmullRom))
+    })?;
+    iprintln!("Full decode + resize: " full;?);
+    let scaled = measure(&fixtures, |buf| {
+        Ok(imaging::thumbnail(buf, None, THUMBNAIL_SIZE)?.image)
+    })?;
+    iprintln!("Scaled decode:        " scaled;?);
+    let speedup = full.as_secs_f64() / scaled.as_secs_f64();
+    iprintln!("Speedup: " format!("{speedup:.1}") "x");
+    Ok(())
+}
+
+fn measure(
+    fixtures: &[Vec<u8>],
+    make_thumbnail: impl Fn(&[u8]) -> Result<DynamicImage>,
+) -> Result<Duration> {
+    let start = Instant::now();
+    for buf in fixtures {
+        make_thumbnail(buf)?;
+    }
+    Ok(start.elapsed())
+}
+
+fn fixtures_from(dir: PathBuf) -> Result<Vec<Vec<u8>>> {
+    let mut fixtures = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        if ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg") {
+            fixtures.push(std::fs::read(path)?);
+        }
+    }
+    Ok(fixtures)
+}
+
+/// Smooth gradients at sizes of phones and cameras, compressed like photos.
+fn generated_fixtures() -> Result<Vec<Vec<u8>>> {
+    let sizes = [(4032, 3024), (3024, 4032), (6000, 4000), (4000, 3000)];
+    let mut fixtures = Vec::new();
+    for (w, h) in sizes {
+        let img = RgbImage::from_fn(w, h, |x, y| {
+            let v = |f: f32| ((f.sin() + 1.0) * 127.0) as u8;
+            image::Rgb([
+                v(x as f32 / 300.0),
+                v(y as f32 / 200.0),
+                v((x + y) as f32 / 500.0),
+            ])
+        });
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(img).write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))?;
+        fixtures.push(jpeg);
+    }
+    Ok(fixtures)
+}
@@ -0,0 +1,26 @@
+use anyhow::Result;
+
+use backer::db;
+use backer::interlude::*;
+
+fn main() {
+    if let Err(err) = run() {
+        ieprintln!("error: " error_chain(&err));
+    }
+}
+
+/// Shows how many thumbnails were made by each decoding path, e.g. to see how many files depend on
+/// fallbacks.
+fn run() -> Result<()> {
+    let db = db::open("backer.db")?;
+    let db = db.lock().unwrap();
+    let mut query = db::thumbnail_sources(&db);
+    for row in query.run(()) {
+        let (source, count) = row?;
+        match source {
+            Some(source) => iprintln!(source;? ": " count),
+            None => iprintln!("unknown: " count),
+        }
+    }
+    Ok(())
+}
@@ -3,16 +3,75 @@ use iced::Application;
 use tracing_chrome::ChromeLayerBuilder as ChromiumTracingBuilder;
 use tracing_subscriber::prelude::*;
 
-use backer::db;
+use backer::db::{self, AreaFilter, CameraFilter, PlaceFilter};
 use backer::gui::Gui;
 
+const USAGE: &str = "usage: view [--bbox SOUTH,WEST,NORTH,EAST | --near LAT,LON,KM] \
+                     [--country CODE] [--region NAME] [--city NAME] \
+                     [--make NAME] [--model NAME] [--lens NAME] [--focal MM | --focal MIN,MAX]";
+
+#[derive(Default)]
+struct Filters {
+    area: Option<AreaFilter>,
+    place: Option<PlaceFilter>,
+    camera: Option<CameraFilter>,
+}
+
 fn main() -> iced::Result {
     println!("Hello view");
 
+    let Some(filters) = filters_from_args() else {
+        eprintln!("{USAGE}");
+        std::process::exit(1);
+    };
+
     let (chromium_tracing, _guard) = ChromiumTracingBuilder::new().build();
     tracing_subscriber::registry().with(chromium_tracing).init();
 
     let db = db::open("backer.db").unwrap();
+    db::set_area_filter(&db.lock().unwrap(), filters.area.as_ref()).unwrap();
+    db::set_place_filter(&db.lock().unwrap(), filters.place.as_ref()).unwrap();
+    db::set_camera_filter(&db.lock().unwrap(), filters.camera.as_ref()).unwrap();
 
     Gui::run(iced::Settings::with_flags(db))
 }
+
+/// Parses the optional gallery filters; returns None if arguments are invalid.
+fn filters_from_args() -> Option<Filters> {
+    let numbers = |s: &str| {
+        s.split(',')
+            .map(|v| v.trim().parse::<f64>().ok())
+            .collect::<Option<Vec<_>>>()
+    };
+    let mut filters = Filters::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next()?;
+        let place = &mut filters.place;
+        let camera = &mut filters.camera;
+        match (arg.as_str(), &numbers(&value).unwrap_or_default()[..]) {
+            ("--bbox", &[south, west, north, east]) => {
+                filters.area = Some(AreaFilter::BoundingBox {
+                    south,
+                    west,
+                    north,
+                    east,
+                })
+            }
+            ("--near", &[lat, lon, km]) => filters.area = Some(AreaFilter::Radius { lat, lon, km }),
+            ("--country", _) => place.get_or_insert_with(Default::default).country = Some(value),
+            ("--region", _) => place.get_or_insert_with(Default::default).region = Some(value),
+            ("--city", _) => place.get_or_insert_with(Default::default).city = Some(value),
+            ("--make", _) => camera.get_or_insert_with(Default::default).make = Some(value),
+            ("--model", _) => camera.get_or_insert_with(Default::default).model = Some(value),
+            ("--lens", _) => camera.get_or_insert_with(Default::default).lens = Some(value),
+            ("--focal", &[min, ref max @ ..]) if max.len() <= 1 => {
+                let camera = camera.get_or_insert_with(Default::default);
+                camera.min_focal_length = Some(min);
+                camera.max_focal_length = Some(max.first().copied().unwrap_or(min));
+            }
+            _ => return None,
+        }
+    }
+    Some(filters)
+}
@@ -4,25 +4,352 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
+use chrono::FixedOffset;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::imaging::DateTag;
 use crate::interlude::*;
+use crate::model::HashAlgorithm;
+use crate::pathwalk::walker::Symlinks;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub markers: Markers,
     pub date_path: DatePathsPerMarker,
+    #[serde(default)]
+    pub ignore_small: Option<IgnoreSmall>,
+    /// Glob patterns of paths (relative to the root of a tree) to skip in all trees.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub symlinks: Option<Symlinks>,
+    /// If set, files with no date found in metadata nor by `date_path` get one from the filesystem.
+    #[serde(default)]
+    pub file_date: Option<FileDate>,
+    /// Exif tags to take the date of a photo from, in order of preference.
+    #[serde(default)]
+    pub date_tags: Option<Vec<DateTag>>,
+    /// Timezone assumed for dates which don't have one.
+    #[serde(default)]
+    pub timezone: Option<Timezone>,
+    /// If set, GPS coordinates of files are resolved to names of places.
+    #[serde(default)]
+    pub geocoding: Option<Geocoding>,
+    /// If set, scanning catalogs files without thumbnails first, and a background worker makes
+    /// them afterwards.
+    #[serde(default)]
+    pub defer_thumbnails: bool,
+    /// Limit of megabytes of file contents held in memory at once while scanning, across all trees
+    /// scanned in parallel; unlimited if not set.
+    #[serde(default)]
+    pub max_in_flight_mb: Option<u64>,
+    /// Algorithms of digests calculated for files when scanning, besides the SHA-1 identifying
+    /// them, e.g. to verify files with a stronger one. Files cataloged before an algorithm was
+    /// added get their digests from [crate::hashing::backfill].
+    #[serde(default)]
+    pub digests: Vec<HashAlgorithm>,
+    /// Settings overriding the global ones for specific markers.
+    #[serde(default)]
+    pub marker: HashMap<String, MarkerConfig>,
 }
 
 pub type DatePathsPerMarker = HashMap<String, Vec<DatePath>>;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Paths of marker files. They may start with `~`, and contain environment variables as `$VAR` or
+/// `${VAR}`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Markers {
+    /// Markers on all hosts.
+    #[serde(default)]
+    pub disk: Vec<PathBuf>,
+    /// Markers only on the host with a given name (compared case-insensitively).
+    #[serde(default)]
+    pub host: HashMap<String, HostMarkers>,
+    /// Markers only on a given OS, as in `std::env::consts::OS`: "windows", "linux", "macos"...
+    #[serde(default)]
+    pub os: HashMap<String, HostMarkers>,
+    /// If set, marker files of known trees are also searched for on mounted volumes.
+    #[serde(default)]
+    pub discover: Option<Discover>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HostMarkers {
+    #[serde(default)]
     pub disk: Vec<PathBuf>,
 }
 
+/// Marker paths resolved for a specific host.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HostMarkerPaths {
+    pub disk: Vec<PathBuf>,
+    /// Configured paths which don't apply to the host, with explanation why.
+    pub not_applicable: Vec<(PathBuf, String)>,
+}
+
+impl Markers {
+    pub fn for_current_host(&self) -> HostMarkerPaths {
+        let hostname = gethostname::gethostname();
+        self.for_host(&hostname.to_string_lossy(), std::env::consts::OS, |var| {
+            std::env::var(var).ok()
+        })
+    }
+
+    /// Selects marker paths applying to the host, expanding them with `env` lookups.
+    pub fn for_host(
+        &self,
+        hostname: &str,
+        os: &str,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> HostMarkerPaths {
+        let mut resolved = HostMarkerPaths::default();
+        let mut sections = vec![(&self.disk, None)];
+        for (name, markers) in &self.host {
+            let other = !name.eq_ignore_ascii_case(hostname);
+            sections.push((&markers.disk, other.then(|| ifmt!("only for host " name;?))));
+        }
+        for (name, markers) in &self.os {
+            let other = name != os;
+            sections.push((&markers.disk, other.then(|| ifmt!("only for OS " name;?))));
+        }
+        for (paths, other) in sections {
+            for path in paths {
+                if let Some(reason) = &other {
+                    resolved.not_applicable.push((path.clone(), reason.clone()));
+                    continue;
+                }
+                match expand(path, &env) {
+                    Ok(expanded) if expanded.is_absolute() => resolved.disk.push(expanded),
+                    Ok(_) => resolved
+                        .not_applicable
+                        .push((path.clone(), ifmt!("not an absolute path on " os))),
+                    Err(reason) => resolved.not_applicable.push((path.clone(), reason)),
+                }
+            }
+        }
+        resolved
+    }
+}
+
+/// Expands a leading `~` to the home directory, and `$VAR` or `${VAR}` to environment variables.
+fn expand(path: &Path, env: impl Fn(&str) -> Option<String>) -> Result<PathBuf, String> {
+    let raw = path
+        .to_str()
+        .ok_or_else(|| "path is not valid UTF-8".to_string())?;
+    let lookup =
+        |var: &str| env(var).ok_or_else(|| ifmt!("environment variable " var;? " not set"));
+    let mut expanded = String::new();
+    let mut rest = raw;
+    if let Some(tail) = raw.strip_prefix('~') {
+        if tail.is_empty() || tail.starts_with(['/', '\\']) {
+            let home = env("HOME").or_else(|| env("USERPROFILE"));
+            expanded += &home.ok_or_else(|| "home directory unknown".to_string())?;
+            rest = tail;
+        }
+    }
+    while let Some(i) = rest.find('$') {
+        expanded += &rest[..i];
+        rest = &rest[i + 1..];
+        let (var, tail) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| "unterminated ${...}".to_string())?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        if var.is_empty() {
+            expanded.push('$');
+        } else {
+            expanded += &lookup(var)?;
+        }
+        rest = tail;
+    }
+    expanded += rest;
+    Ok(PathBuf::from(expanded))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Discover {
+    /// Directories where volumes get mounted, e.g. `/media` or `/run/media`.
+    pub roots: Vec<PathBuf>,
+    /// How deep below a root may a marker file be found, e.g. `/media/$USER/DISK/backer-id.json`
+    /// is at depth 3.
+    #[serde(default = "Discover::default_depth")]
+    pub depth: usize,
+}
+
+impl Discover {
+    fn default_depth() -> usize {
+        3
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MarkerConfig {
+    pub ignore_small: Option<IgnoreSmall>,
+    /// Glob patterns to skip in the tree, in addition to the global ones.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    pub symlinks: Option<Symlinks>,
+    pub file_date: Option<FileDate>,
+    pub date_tags: Option<Vec<DateTag>>,
+    pub timezone: Option<Timezone>,
+}
+
+/// Offline reverse geocoding with GeoNames dump files, see
+/// https://download.geonames.org/export/dump/readme.txt
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Geocoding {
+    /// Populated places, e.g. `cities1000.txt`.
+    pub cities: PathBuf,
+    /// Names of regions, i.e. `admin1CodesASCII.txt`.
+    #[serde(default)]
+    pub admin1: Option<PathBuf>,
+    /// Places farther from the coordinates are not considered.
+    #[serde(default = "Geocoding::default_max_km")]
+    pub max_km: f64,
+}
+
+impl Geocoding {
+    fn default_max_km() -> f64 {
+        50.0
+    }
+}
+
+/// Fixed offset from UTC, written like `+02:00`, `-05:30` or `Z`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Timezone(pub FixedOffset);
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let invalid = || ifmt!("invalid timezone " s;? ", expected e.g. \"+02:00\"");
+        if s == "Z" || s == "UTC" {
+            return Ok(Timezone(FixedOffset::east(0)));
+        }
+        let sign = match s.get(..1) {
+            Some("+") => 1,
+            Some("-") => -1,
+            _ => return Err(invalid()),
+        };
+        let (h, m) = s[1..].split_once(':').unwrap_or((&s[1..], "0"));
+        let (h, m): (i32, i32) = match (h.parse(), m.parse()) {
+            (Ok(h), Ok(m)) if m < 60 => (h, m),
+            _ => return Err(invalid()),
+        };
+        FixedOffset::east_opt(sign * (h * 3600 + m * 60))
+            .map(Timezone)
+            .ok_or_else(invalid)
+    }
+}
+
+impl From<Timezone> for String {
+    fn from(tz: Timezone) -> Self {
+        tz.0.to_string()
+    }
+}
+
+/// Which filesystem timestamp to use as a fallback date of a file.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileDate {
+    Modified,
+    /// Birth time, where the filesystem provides it. Copying a file usually resets it while
+    /// keeping the modification time, so the earlier of the two is used.
+    Created,
+}
+
+/// Limits below which images are assumed to be thumbnails, which we don't want to archive.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IgnoreSmall {
+    /// Width and height in pixels; an image is small only if it's below both (when both are set).
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    /// Size of the file.
+    pub bytes: Option<u64>,
+}
+
+impl IgnoreSmall {
+    /// Checks the file against the limits; `dimensions` (width, height) are only checked if known.
+    pub fn is_small(&self, bytes: u64, dimensions: Option<(u32, u32)>) -> bool {
+        if matches!(self.bytes, Some(limit) if bytes < limit) {
+            return true;
+        }
+        match (dimensions, self.w, self.h) {
+            (None, _, _) | (_, None, None) => false,
+            (Some((w, h)), limit_w, limit_h) => {
+                limit_w.map_or(true, |limit| w < limit) && limit_h.map_or(true, |limit| h < limit)
+            }
+        }
+    }
+}
+
+impl Config {
+    pub fn ignore_small_for(&self, marker: &str) -> Option<&IgnoreSmall> {
+        self.marker
+            .get(marker)
+            .and_then(|m| m.ignore_small.as_ref())
+            .or(self.ignore_small.as_ref())
+    }
+
+    pub fn symlinks_for(&self, marker: &str) -> Symlinks {
+        self.marker
+            .get(marker)
+            .and_then(|m| m.symlinks)
+            .or(self.symlinks)
+            .unwrap_or_default()
+    }
+
+    pub fn file_date_for(&self, marker: &str) -> Option<FileDate> {
+        self.marker
+            .get(marker)
+            .and_then(|m| m.file_date)
+            .or(self.file_date)
+    }
+
+    pub fn date_tags_for(&self, marker: &str) -> &[DateTag] {
+        self.marker
+            .get(marker)
+            .and_then(|m| m.date_tags.as_deref())
+            .or(self.date_tags.as_deref())
+            .unwrap_or(DateTag::DEFAULT_PRIORITY)
+    }
+
+    pub fn timezone_for(&self, marker: &str) -> Option<FixedOffset> {
+        self.marker
+            .get(marker)
+            .and_then(|m| m.timezone)
+            .or(self.timezone)
+            .map(|tz| tz.0)
+    }
+
+    pub fn exclude_for(&self, marker: &str) -> impl Iterator<Item = &String> {
+        let specific = self.marker.get(marker).map(|m| &m.exclude);
+        self.exclude.iter().chain(specific.into_iter().flatten())
+    }
+
+    /// All algorithms of digests to calculate when scanning, starting with SHA-1.
+    pub fn hash_algorithms(&self) -> Vec<HashAlgorithm> {
+        let mut algorithms = vec![HashAlgorithm::Sha1];
+        for &algorithm in &self.digests {
+            if !algorithms.contains(&algorithm) {
+                algorithms.push(algorithm);
+            }
+        }
+        algorithms
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DatePath {
     pub date: String,
@@ -35,3 +362,148 @@ pub fn read<P: AsRef<Path> + Display>(path: P) -> Result<Config> {
     let config = toml::from_str(&raw).with_context(|| ifmt!("reading config file '{path}'"))?;
     Ok(config)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ignore_small_per_marker() {
+        let config: Config = toml::from_str(
+            r#"
+ignore-small = { w = 1024, h = 1024 }
+exclude = ["**/.thumbnails"]
+
+[markers]
+disk = []
+
+[date-path]
+
+[marker.foo-marker]
+ignore-small = { bytes = 20000 }
+exclude = ["cache"]
+symlinks = "alias"
+file-date = "created"
+date-tags = ["DateTime"]
+timezone = "-05:30"
+"#,
+        )
+        .unwrap();
+
+        let global = config.ignore_small_for("bar-marker").unwrap();
+        assert!(global.is_small(100, Some((640, 480))));
+        assert!(!global.is_small(100, Some((4000, 480))));
+        assert!(!global.is_small(100, None));
+
+        let foo = config.ignore_small_for("foo-marker").unwrap();
+        assert!(foo.is_small(100, Some((4000, 3000))));
+        assert!(!foo.is_small(20000, Some((640, 480))));
+
+        assert_eq!(
+            config.exclude_for("foo-marker").collect::<Vec<_>>(),
+            vec!["**/.thumbnails", "cache"]
+        );
+        assert_eq!(
+            config.exclude_for("bar-marker").collect::<Vec<_>>(),
+            vec!["**/.thumbnails"]
+        );
+
+        assert_eq!(config.symlinks_for("foo-marker"), Symlinks::Alias);
+        assert_eq!(config.symlinks_for("bar-marker"), Symlinks::Error);
+
+        assert_eq!(config.file_date_for("foo-marker"), Some(FileDate::Created));
+        assert_eq!(config.file_date_for("bar-marker"), None);
+
+        assert_eq!(config.date_tags_for("foo-marker"), &[DateTag::DateTime]);
+        assert_eq!(
+            config.date_tags_for("bar-marker"),
+            DateTag::DEFAULT_PRIORITY
+        );
+
+        assert_eq!(
+            config.timezone_for("foo-marker"),
+            FixedOffset::west_opt(5 * 3600 + 30 * 60)
+        );
+        assert_eq!(config.timezone_for("bar-marker"), None);
+    }
+
+    #[test]
+    fn timezone_syntax() {
+        let tz = |s: &str| Timezone::try_from(s.to_string()).map(|tz| tz.0.local_minus_utc());
+        assert_eq!(tz("+02:00"), Ok(7200));
+        assert_eq!(tz("-09:30"), Ok(-34200));
+        assert_eq!(tz("+01"), Ok(3600));
+        assert_eq!(tz("Z"), Ok(0));
+        assert!(tz("02:00").is_err());
+        assert!(tz("+02:75").is_err());
+        assert!(tz("+25:00").is_err());
+        assert_eq!(String::from(Timezone(FixedOffset::east(7200))), "+02:00");
+    }
+
+    #[test]
+    fn markers_for_host() {
+        let config: Config = toml::from_str(
+            r#"
+[markers]
+disk = ['d:\backer-id.json', '/mnt/${DISK}/backer-id.json']
+
+[markers.host.LAPTOP]
+disk = ['~/fotki/backer-id.json']
+
+[markers.host.desktop]
+disk = ['/home/bar/backer-id.json']
+
+[markers.os.windows]
+disk = ['c:\fotki\backer-id.json']
+
+[date-path]
+"#,
+        )
+        .unwrap();
+        let env = |var: &str| match var {
+            "HOME" => Some("/home/foo".to_string()),
+            "DISK" => Some("usb".to_string()),
+            _ => None,
+        };
+
+        let mut resolved = config.markers.for_host("laptop", "linux", env);
+        resolved.not_applicable.sort();
+
+        assert_eq!(
+            resolved.disk,
+            vec![
+                PathBuf::from("/mnt/usb/backer-id.json"),
+                PathBuf::from("/home/foo/fotki/backer-id.json"),
+            ]
+        );
+        assert_eq!(
+            resolved.not_applicable,
+            vec![
+                (
+                    PathBuf::from("/home/bar/backer-id.json"),
+                    r#"only for host "desktop""#.to_string()
+                ),
+                (
+                    PathBuf::from("c:\\fotki\\backer-id.json"),
+                    r#"only for OS "windows""#.to_string()
+                ),
+                (
+                    PathBuf::from("d:\\backer-id.json"),
+                    "not an absolute path on linux".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_env() {
+        let env = |var: &str| (var == "FOO").then(|| "foo".to_string());
+        assert_eq!(
+            expand(Path::new("/$FOO/${FOO}x/$$"), env),
+            Ok("/foo/foox/$$".into())
+        );
+        assert!(expand(Path::new("/$BAR"), env).is_err());
+        assert!(expand(Path::new("~/x"), env).is_err());
+        assert_eq!(expand(Path::new("~x"), env), Ok("~x".into()));
+    }
+}
@@ -1,10 +1,16 @@
 use std::path::Path;
 
 use anyhow::Result;
+use chrono::{FixedOffset, NaiveDateTime};
 use const_format::concatcp;
-use rusqlite::{params, Connection, Error::QueryReturnedNoRows};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, Error::QueryReturnedNoRows, OptionalExtension};
 
+use crate::imaging::{Orientation, THUMBNAIL_SIZE};
 use crate::interlude::*;
+use crate::model::{
+    Camera, DateSource, GpsPosition, HashAlgorithm, MediaType, Place, ProblemKind, ThumbnailSource,
+};
 
 mod typed_query;
 pub use typed_query::*;
@@ -38,13 +44,15 @@ pub fn open(path: impl AsRef<Path>) -> Result<SyncedDb> {
 
 pub fn init(db: &Connection) -> rusqlite::Result<()> {
     rusqlite::vtab::array::load_module(&db)?;
+    // Like NOCASE, but folding the case of all letters, not only ASCII ones, e.g. in place names.
+    db.create_collation("UNICASE", |a, b| a.to_lowercase().cmp(&b.to_lowercase()))?;
     db.execute_batch(
         "
           CREATE TABLE IF NOT EXISTS file (
             hash TEXT UNIQUE NOT NULL
               CHECK(length(hash) > 0),
             date TEXT,
-            thumbnail BLOB
+            thumbnail BLOB -- moved to the thumbnail table by migration 10
           );
           CREATE INDEX IF NOT EXISTS file_date ON file(date);
 
@@ -73,8 +81,412 @@ pub fn init(db: &Connection) -> rusqlite::Result<()> {
           );
           CREATE UNIQUE INDEX IF NOT EXISTS
             file_tag_unique ON file_tag (file_id, tag_id);
+
+          -- Files deliberately not cataloged during the latest scan, with explanation why.
+          CREATE TABLE IF NOT EXISTS skipped (
+            backend_tag TEXT NOT NULL,
+            path TEXT NOT NULL,
+            reason TEXT NOT NULL
+          );
+          CREATE UNIQUE INDEX IF NOT EXISTS
+            skipped_perBackend ON skipped (backend_tag, path);
+
+          -- Files which could not be cataloged during the latest scan due to errors.
+          CREATE TABLE IF NOT EXISTS scan_problem (
+            backend_tag TEXT NOT NULL,
+            path TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            message TEXT NOT NULL
+          );
+          CREATE UNIQUE INDEX IF NOT EXISTS
+            scan_problem_perBackend ON scan_problem (backend_tag, path);
+
+          -- RAW files shot together with a JPEG are shown as a single asset.
+          CREATE TABLE IF NOT EXISTS raw_pair (
+            raw_file_id INTEGER UNIQUE NOT NULL,
+            jpeg_file_id INTEGER NOT NULL
+          );
+
+          -- Metadata of files, extracted from Exif.
+          CREATE TABLE IF NOT EXISTS file_meta (
+            file_id INTEGER UNIQUE NOT NULL,
+            lat REAL,
+            lon REAL,
+            alt REAL
+          );
+          CREATE INDEX IF NOT EXISTS
+            file_meta_latLon ON file_meta (lat, lon);
+
+          -- Areas to which the gallery is currently limited (see: set_area_filter).
+          CREATE TEMP TABLE IF NOT EXISTS area_filter (
+            south REAL NOT NULL,
+            west REAL NOT NULL,
+            north REAL NOT NULL,
+            east REAL NOT NULL,
+            -- Only for a radius filter:
+            lat REAL,
+            lon REAL,
+            km REAL,
+            lon_scale REAL
+          );
+
+          -- Places to which the gallery is currently limited (see: set_place_filter).
+          CREATE TEMP TABLE IF NOT EXISTS place_filter (
+            country TEXT,
+            region TEXT,
+            city TEXT
+          );
+
+          -- Cameras and lenses to which the gallery is currently limited (see: set_camera_filter).
+          CREATE TEMP TABLE IF NOT EXISTS camera_filter (
+            make TEXT,
+            model TEXT,
+            lens TEXT,
+            min_focal_length REAL,
+            max_focal_length REAL
+          );
+
+          -- JPEG thumbnails of files, fitting in a square of `size` pixels; the ones of
+          -- imaging::THUMBNAIL_SIZE are made when scanning, others on demand (see: thumbnails).
+          CREATE TABLE IF NOT EXISTS thumbnail (
+            file_id INTEGER NOT NULL,
+            size INTEGER NOT NULL,
+            jpeg BLOB NOT NULL
+          );
+          CREATE UNIQUE INDEX IF NOT EXISTS
+            thumbnail_perSize ON thumbnail (file_id, size);
+
+          -- Digests of file contents by algorithms other than the SHA-1 which identifies files in
+          -- the `file` table (see: hashing).
+          CREATE TABLE IF NOT EXISTS digest (
+            file_id INTEGER NOT NULL,
+            algorithm TEXT NOT NULL,
+            digest TEXT NOT NULL
+          );
+          CREATE UNIQUE INDEX IF NOT EXISTS
+            digest_perAlgorithm ON digest (file_id, algorithm);
+
+          -- Scrubs started but not finished yet, e.g. interrupted, to be resumed (see: scrub).
+          CREATE TABLE IF NOT EXISTS scrub_run (
+            backend_tag TEXT UNIQUE NOT NULL,
+            started TEXT NOT NULL,
+            target INTEGER NOT NULL -- number of locations to verify
+          );
+
+          -- Locations with contents not matching the digest of their file when last scrubbed.
+          CREATE TABLE IF NOT EXISTS scrub_mismatch (
+            backend_tag TEXT NOT NULL,
+            path TEXT NOT NULL,
+            algorithm TEXT NOT NULL,
+            expected TEXT NOT NULL,
+            actual TEXT NOT NULL,
+            found TEXT NOT NULL
+          );
+          CREATE UNIQUE INDEX IF NOT EXISTS
+            scrub_mismatch_perBackend ON scrub_mismatch (backend_tag, path);
+
+          -- Where, and with which UUID, was each marker last scanned, to detect cloned trees.
+          CREATE TABLE IF NOT EXISTS marker_seen (
+            backend_tag TEXT UNIQUE NOT NULL,
+            root TEXT NOT NULL,
+            uuid TEXT
+          );
         ",
+    )?;
+    migrate(db)
+}
+
+/// Changes to the tables defined in [init], applied in order. The number of already applied
+/// migrations is tracked in SQLite's `user_version` pragma, so only ever append to this list!
+const MIGRATIONS: &[&str] = &[
+    // 1
+    "ALTER TABLE file ADD COLUMN media TEXT NOT NULL DEFAULT 'image'",
+    // 2: for symlinks, path of the target at the same backend
+    "ALTER TABLE location ADD COLUMN alias_of TEXT",
+    // 3: NULL for dates found before this was tracked, which came from EXIF or DatePath rules
+    "ALTER TABLE file ADD COLUMN date_source TEXT",
+    // 4: offset of the local `date` from UTC in seconds, and the `date` converted to UTC for
+    // ordering (same as `date` if the offset is unknown)
+    "ALTER TABLE file ADD COLUMN date_offset INTEGER;
+     ALTER TABLE file ADD COLUMN instant TEXT;
+     UPDATE file SET instant = date;
+     CREATE INDEX file_instant ON file(instant)",
+    // 5: names of the place nearest to the GPS coordinates
+    "ALTER TABLE file_meta ADD COLUMN country TEXT;
+     ALTER TABLE file_meta ADD COLUMN region TEXT;
+     ALTER TABLE file_meta ADD COLUMN city TEXT;
+     CREATE INDEX file_meta_place ON file_meta (country, region, city)",
+    // 6: camera, lens and exposure settings, and size in pixels
+    "ALTER TABLE file_meta ADD COLUMN make TEXT;
+     ALTER TABLE file_meta ADD COLUMN model TEXT;
+     ALTER TABLE file_meta ADD COLUMN lens TEXT;
+     ALTER TABLE file_meta ADD COLUMN focal_length REAL;
+     ALTER TABLE file_meta ADD COLUMN iso INTEGER;
+     ALTER TABLE file_meta ADD COLUMN exposure REAL;
+     ALTER TABLE file_meta ADD COLUMN width INTEGER;
+     ALTER TABLE file_meta ADD COLUMN height INTEGER;
+     CREATE INDEX file_meta_camera ON file_meta (make, model);
+     CREATE INDEX file_meta_lens ON file_meta (lens)",
+    // 7: Exif orientation which the thumbnail was corrected for; NULL for thumbnails created
+    // before they were deoriented (see: scanning::reorient_thumbnails), videos need no correction
+    "ALTER TABLE file ADD COLUMN orientation INTEGER;
+     UPDATE file SET orientation = 1 WHERE media = 'video'",
+    // 8: perceptual hash of the thumbnail (see: imaging::dhash), stored as signed integer
+    "ALTER TABLE file ADD COLUMN phash INTEGER",
+    // 9: how the thumbnail was made; NULL for thumbnails made before this was tracked
+    "ALTER TABLE file ADD COLUMN thumbnail_source TEXT",
+    // 10: thumbnails, all 200px so far, moved to their own table to allow more sizes
+    "INSERT INTO thumbnail(file_id, size, jpeg)
+       SELECT rowid, 200, thumbnail FROM file WHERE length(thumbnail) > 0;
+     ALTER TABLE file DROP COLUMN thumbnail",
+    // 11: when contents at the location were last verified by scrubbing (see: scrub); NULL if
+    // never
+    "ALTER TABLE location ADD COLUMN verified TEXT",
+    // 12: name of the marker file in the root; NULL for markers seen before this was tracked,
+    // which are assumed to have the default name (see: marker::FILE_NAME)
+    "ALTER TABLE marker_seen ADD COLUMN file_name TEXT",
+    // 13: when the location was last attempted by scrubbing, also if its file was missing or
+    // could not be read; it orders scrubs, so that such locations don't block the rest
+    "ALTER TABLE location ADD COLUMN scrubbed TEXT;
+     UPDATE location SET scrubbed = verified",
+];
+
+fn migrate(db: &Connection) -> rusqlite::Result<()> {
+    apply_migrations(db, MIGRATIONS)
+}
+
+/// Applies each of the `migrations` not applied yet in its own transaction, so that a failing one
+/// is rolled back whole, and leaves the version at the last one which succeeded.
+fn apply_migrations(db: &Connection, migrations: &[&str]) -> rusqlite::Result<()> {
+    let applied: usize = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, sql) in migrations.iter().enumerate().skip(applied) {
+        let version = i + 1;
+        let tx = db.unchecked_transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+impl ToSql for DateSource {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            DateSource::Exif => "exif",
+            DateSource::Container => "container",
+            DateSource::Path => "path",
+            DateSource::Filesystem => "filesystem",
+        }
+        .into())
+    }
+}
+
+impl FromSql for DateSource {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "exif" => Ok(DateSource::Exif),
+            "container" => Ok(DateSource::Container),
+            "path" => Ok(DateSource::Path),
+            "filesystem" => Ok(DateSource::Filesystem),
+            other => Err(FromSqlError::Other(
+                anyhow!("unknown date source: {other}").into(),
+            )),
+        }
+    }
+}
+
+impl ToSql for MediaType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            MediaType::Image => "image",
+            MediaType::Video => "video",
+        }
+        .into())
+    }
+}
+
+impl FromSql for MediaType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "image" => Ok(MediaType::Image),
+            "video" => Ok(MediaType::Video),
+            other => Err(FromSqlError::Other(
+                anyhow!("unknown media type: {other}").into(),
+            )),
+        }
+    }
+}
+
+impl ToSql for ThumbnailSource {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            ThumbnailSource::Decoder => "decoder",
+            ThumbnailSource::FallbackDecoder => "fallback-decoder",
+            ThumbnailSource::ExifThumbnail => "exif-thumbnail",
+            ThumbnailSource::Placeholder => "placeholder",
+            ThumbnailSource::Pending => "pending",
+        }
+        .into())
+    }
+}
+
+impl FromSql for ThumbnailSource {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "decoder" => Ok(ThumbnailSource::Decoder),
+            "fallback-decoder" => Ok(ThumbnailSource::FallbackDecoder),
+            "exif-thumbnail" => Ok(ThumbnailSource::ExifThumbnail),
+            "placeholder" => Ok(ThumbnailSource::Placeholder),
+            "pending" => Ok(ThumbnailSource::Pending),
+            other => Err(FromSqlError::Other(
+                anyhow!("unknown thumbnail source: {other}").into(),
+            )),
+        }
+    }
+}
+
+impl ToSql for ProblemKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            ProblemKind::Access => "access",
+            ProblemKind::Read => "read",
+            ProblemKind::Decode => "decode",
+            ProblemKind::NoPreview => "no-preview",
+        }
+        .into())
+    }
+}
+
+impl FromSql for ProblemKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "access" => Ok(ProblemKind::Access),
+            "read" => Ok(ProblemKind::Read),
+            "decode" => Ok(ProblemKind::Decode),
+            "no-preview" => Ok(ProblemKind::NoPreview),
+            other => Err(FromSqlError::Other(
+                anyhow!("unknown problem kind: {other}").into(),
+            )),
+        }
+    }
+}
+
+impl ToSql for HashAlgorithm {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+        .into())
+    }
+}
+
+impl FromSql for HashAlgorithm {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            other => Err(FromSqlError::Other(
+                anyhow!("unknown hash algorithm: {other}").into(),
+            )),
+        }
+    }
+}
+
+/// Whether any file locations are cataloged for the `marker`.
+pub fn marker_known(db: &Connection, marker: &str) -> rusqlite::Result<bool> {
+    db.query_row(
+        "SELECT EXISTS(SELECT 1 FROM location
+            WHERE backend_tag = ?)",
+        params![marker],
+        |row| row.get(0),
+    )
+}
+
+/// Ids of all markers with any file locations cataloged.
+pub fn known_markers(db: &Connection) -> rusqlite::Result<Vec<String>> {
+    db.prepare("SELECT DISTINCT backend_tag FROM location")?
+        .query_map([], |row| row.get(0))?
+        .collect()
+}
+
+/// Root, marker file name, and UUID of the marker where it was last seen.
+pub type MarkerSeen = (String, Option<String>, Option<String>);
+
+/// Returns where the `marker` was seen during its latest scan.
+pub fn marker_seen(db: &Connection, marker: &str) -> rusqlite::Result<Option<MarkerSeen>> {
+    db.query_row(
+        "SELECT root, file_name, uuid FROM marker_seen
+            WHERE backend_tag = ?",
+        params![marker],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
     )
+    .optional()
+}
+
+pub fn set_marker_seen(
+    db: &Connection,
+    marker: &str,
+    root: &str,
+    file_name: &str,
+    uuid: Option<&str>,
+) -> Result<()> {
+    db.execute(
+        "INSERT INTO marker_seen(backend_tag,root,file_name,uuid) VALUES(?,?,?,?)
+            ON CONFLICT(backend_tag) DO UPDATE SET
+              root = excluded.root,
+              file_name = excluded.file_name,
+              uuid = excluded.uuid",
+        params![&marker, &root, &file_name, &uuid],
+    )?;
+    Ok(())
+}
+
+/// Moves all catalog rows of the `old` marker to the `new` one. If `keep_old` is set, the rows are
+/// copied instead, leaving the `old` marker intact. Should be called inside a transaction.
+pub fn rename_marker(db: &Connection, old: &str, new: &str, keep_old: bool) -> Result<()> {
+    if keep_old {
+        db.execute(
+            "INSERT INTO location(file_id,backend_tag,path,alias_of,verified,scrubbed)
+                SELECT file_id, ?, path, alias_of, verified, scrubbed FROM location
+                  WHERE backend_tag = ?",
+            params![&new, &old],
+        )?;
+        db.execute(
+            "INSERT INTO skipped(backend_tag,path,reason)
+                SELECT ?, path, reason FROM skipped
+                  WHERE backend_tag = ?",
+            params![&new, &old],
+        )?;
+        db.execute(
+            "INSERT INTO scan_problem(backend_tag,path,kind,message)
+                SELECT ?, path, kind, message FROM scan_problem
+                  WHERE backend_tag = ?",
+            params![&new, &old],
+        )?;
+        db.execute(
+            "INSERT INTO scrub_mismatch(backend_tag,path,algorithm,expected,actual,found)
+                SELECT ?, path, algorithm, expected, actual, found FROM scrub_mismatch
+                  WHERE backend_tag = ?",
+            params![&new, &old],
+        )?;
+    } else {
+        let tables = [
+            "location",
+            "skipped",
+            "scan_problem",
+            "scrub_run",
+            "scrub_mismatch",
+            "marker_seen",
+        ];
+        for table in tables {
+            db.execute(
+                &format!("UPDATE {table} SET backend_tag = ? WHERE backend_tag = ?"),
+                params![&new, &old],
+            )?;
+        }
+    }
+    Ok(())
 }
 
 pub fn exists(db: &Connection, marker: &str, relative: &str) -> ::rusqlite::Result<bool> {
@@ -88,120 +500,1042 @@ pub fn exists(db: &Connection, marker: &str, relative: &str) -> ::rusqlite::Resu
 }
 
 // FIXME[LATER]: somehow resolve if same hash at different locations gets attributed a different date
+/// Adds the file at the location. A date already known for the file is kept, unless it's from the
+/// filesystem and a more reliable one is found.
 pub fn upsert(
     db: &Connection,
     marker: &str,
     relative: &str,
-    info: &crate::model::FileInfo,
+    info: &crate::model::FileInfo,
+) -> Result<()> {
+    const REPLACE_DATE: &str = "date IS NULL
+        OR (date_source IS 'filesystem'
+          AND excluded.date IS NOT NULL
+          AND excluded.date_source IS NOT 'filesystem')";
+    db.execute(
+        &format!(
+            "INSERT INTO file(hash,date,date_offset,date_source,instant,media)
+                VALUES(?,?,?,?,?,?)
+            ON CONFLICT(hash) DO UPDATE SET
+                date = CASE WHEN {REPLACE_DATE} THEN excluded.date ELSE date END,
+                date_offset = CASE WHEN {REPLACE_DATE}
+                  THEN excluded.date_offset ELSE date_offset END,
+                date_source = CASE WHEN {REPLACE_DATE}
+                  THEN excluded.date_source ELSE date_source END,
+                instant = CASE WHEN {REPLACE_DATE} THEN excluded.instant ELSE instant END,
+                media = excluded.media"
+        ),
+        params![
+            &info.hash,
+            &info.date,
+            &info.date_offset.map(|o| o.local_minus_utc()),
+            &info.date_source,
+            &info.instant(),
+            &info.media
+        ],
+    )?;
+    db.execute(
+        "INSERT INTO location(file_id,backend_tag,path)
+            SELECT rowid, ?, ? FROM file
+              WHERE hash = ? LIMIT 1
+            ON CONFLICT(backend_tag, path) DO UPDATE SET
+              file_id = excluded.file_id",
+        params![&marker, &relative, &info.hash],
+    )?;
+    // An empty thumbnail is pending, so the file keeps the one it may already have.
+    if !info.thumb.is_empty() {
+        set_thumbnail(db, &info.hash, THUMBNAIL_SIZE, &info.thumb)?;
+    }
+    Ok(())
+}
+
+/// Stores the thumbnail of the file of `size`, replacing the previous one.
+pub fn set_thumbnail(db: &Connection, hash: &str, size: u32, jpeg: &[u8]) -> Result<()> {
+    db.execute(
+        "INSERT INTO thumbnail(file_id,size,jpeg)
+            SELECT rowid, ?, ? FROM file
+              WHERE hash = ?
+            ON CONFLICT(file_id, size) DO UPDATE SET
+              jpeg = excluded.jpeg",
+        params![size, &jpeg, &hash],
+    )?;
+    Ok(())
+}
+
+/// Returns (size, JPEG) of the biggest thumbnail of the file, if it has any.
+pub fn largest_thumbnail(db: &Connection, hash: &str) -> Result<Option<(u32, Vec<u8>)>> {
+    let thumb = db
+        .query_row(
+            "SELECT size, jpeg FROM thumbnail
+                WHERE file_id = (SELECT rowid FROM file WHERE hash = ?)
+                ORDER BY size DESC LIMIT 1",
+            params![&hash],
+            |row| Ok((row.get_unwrap(0), row.get_unwrap(1))),
+        )
+        .optional()?;
+    Ok(thumb)
+}
+
+/// Returns (marker root, relative path, media type) of all locations of the file, in trees that
+/// were scanned at least once.
+pub fn thumbnail_locations(
+    db: &Connection,
+    hash: &str,
+) -> Result<Vec<(String, String, MediaType)>> {
+    let mut stmt = db.prepare_cached(
+        "SELECT root, path, media
+            FROM file
+            JOIN location ON location.file_id = file.rowid
+            JOIN marker_seen ON marker_seen.backend_tag = location.backend_tag
+            WHERE hash = ?",
+    )?;
+    let locations = stmt
+        .query_map(params![&hash], |row| {
+            Ok((row.get_unwrap(0), row.get_unwrap(1), row.get_unwrap(2)))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(locations)
+}
+
+/// Stores the `digest` of the file's contents by the `algorithm`, next to its SHA-1 `hash`.
+pub fn set_digest(
+    db: &Connection,
+    hash: &str,
+    algorithm: HashAlgorithm,
+    digest: &str,
+) -> Result<()> {
+    db.execute(
+        "INSERT INTO digest(file_id,algorithm,digest)
+            SELECT rowid, ?, ? FROM file
+              WHERE hash = ?
+            ON CONFLICT(file_id, algorithm) DO UPDATE SET
+              digest = excluded.digest",
+        params![algorithm, &digest, &hash],
+    )?;
+    Ok(())
+}
+
+/// All known digests of the file, including the SHA-1 `hash` itself.
+pub fn digests(db: &Connection, hash: &str) -> Result<Vec<(HashAlgorithm, String)>> {
+    let mut stmt = db.prepare_cached(
+        "SELECT 'sha1', hash FROM file WHERE hash = ?1
+        UNION ALL
+        SELECT algorithm, digest FROM digest
+            JOIN file ON file.rowid = digest.file_id
+            WHERE hash = ?1",
+    )?;
+    let digests = stmt
+        .query_map(params![&hash], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(digests)
+}
+
+/// Returns (hash, root, path) of locations in trees with known roots, of files which have no
+/// digest by the `algorithm` yet; locations of each file are adjacent.
+pub fn missing_digests(
+    db: &Connection,
+    algorithm: HashAlgorithm,
+) -> Result<Vec<(String, String, String)>> {
+    let mut stmt = db.prepare(
+        "SELECT hash, root, path
+            FROM file
+            JOIN location ON location.file_id = file.rowid
+            JOIN marker_seen ON marker_seen.backend_tag = location.backend_tag
+            WHERE NOT EXISTS (
+              SELECT 1 FROM digest
+                WHERE digest.file_id = file.rowid AND algorithm = ?)
+            ORDER BY hash",
+    )?;
+    let locations = stmt
+        .query_map(params![algorithm], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(locations)
+}
+
+/// Records that the thumbnail of the file was corrected for the `orientation`.
+pub fn set_orientation(db: &Connection, hash: &str, orientation: Orientation) -> Result<()> {
+    db.execute(
+        "UPDATE file SET orientation = ? WHERE hash = ?",
+        params![orientation as u32, &hash],
+    )?;
+    Ok(())
+}
+
+/// Replaces the thumbnail of the file with one corrected for the `orientation`. Thumbnails of
+/// other sizes are removed, as they might have been scaled from the uncorrected one.
+pub fn set_oriented_thumbnail(
+    db: &Connection,
+    hash: &str,
+    thumb: &[u8],
+    orientation: Orientation,
+) -> Result<()> {
+    db.execute(
+        "DELETE FROM thumbnail WHERE file_id = (SELECT rowid FROM file WHERE hash = ?)",
+        params![&hash],
+    )?;
+    set_thumbnail(db, hash, THUMBNAIL_SIZE, thumb)?;
+    set_orientation(db, hash, orientation)?;
+    Ok(())
+}
+
+pub fn set_thumbnail_source(db: &Connection, hash: &str, source: ThumbnailSource) -> Result<()> {
+    db.execute(
+        "UPDATE file SET thumbnail_source = ? WHERE hash = ?",
+        params![&source, &hash],
+    )?;
+    Ok(())
+}
+
+/// Marks the thumbnail of the file as pending, unless it already has one. The perceptual hash is
+/// cleared too, as it's calculated from the thumbnail.
+pub fn set_thumbnail_pending(db: &Connection, hash: &str) -> Result<()> {
+    db.execute(
+        "UPDATE file SET thumbnail_source = 'pending', phash = NULL
+            WHERE hash = ? AND NOT EXISTS (
+              SELECT 1 FROM thumbnail WHERE file_id = file.rowid AND size = ?
+            )",
+        params![&hash, THUMBNAIL_SIZE],
+    )?;
+    Ok(())
+}
+
+/// Replaces the pending thumbnail of the file with a made one.
+pub fn set_made_thumbnail(
+    db: &Connection,
+    hash: &str,
+    thumb: &[u8],
+    source: ThumbnailSource,
+    phash: u64,
+) -> Result<()> {
+    set_thumbnail(db, hash, THUMBNAIL_SIZE, thumb)?;
+    db.execute(
+        "UPDATE file SET thumbnail_source = ?, phash = ? WHERE hash = ?",
+        params![&source, phash as i64, &hash],
+    )?;
+    Ok(())
+}
+
+/// Returns (hash, marker, marker root, relative path, orientation) of all locations of files with
+/// pending thumbnails, in trees that were scanned at least once.
+pub fn pending_thumbnails<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (), (String, String, String, String, Option<u32>)> {
+    let sql = r"
+SELECT hash, location.backend_tag, root, path, orientation
+FROM file
+JOIN location ON location.file_id = file.rowid
+JOIN marker_seen ON marker_seen.backend_tag = location.backend_tag
+WHERE thumbnail_source = 'pending'
+ORDER BY hash";
+    TypedQuery::new(db, sql, |row| {
+        Ok((
+            row.get_unwrap(0),
+            row.get_unwrap(1),
+            row.get_unwrap(2),
+            row.get_unwrap(3),
+            row.get_unwrap(4),
+        ))
+    })
+}
+
+/// Returns (thumbnail source, number of files) of all ways in which thumbnails were made; the
+/// source is `None` for thumbnails made before it was tracked.
+pub fn thumbnail_sources<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (), (Option<ThumbnailSource>, u32)> {
+    let sql = r"
+SELECT thumbnail_source, COUNT(*)
+FROM file
+GROUP BY thumbnail_source
+ORDER BY COUNT(*) DESC";
+    TypedQuery::new(db, sql, |row| Ok((row.get_unwrap(0), row.get_unwrap(1))))
+}
+
+pub fn set_perceptual_hash(db: &Connection, hash: &str, phash: Option<u64>) -> Result<()> {
+    db.execute(
+        "UPDATE file SET phash = ? WHERE hash = ?",
+        params![phash.map(|v| v as i64), &hash],
+    )?;
+    Ok(())
+}
+
+/// Returns (rowid, perceptual hash) of files shown in the gallery, in the gallery's order.
+pub fn visible_perceptual_hashes<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (), (Rowid, Option<u64>)> {
+    let sql = concatcp!("SELECT rowid, phash", FROM_VISIBLE_FILE);
+    TypedQuery::new(db, sql, |row| {
+        let phash: Option<i64> = row.get_unwrap(1);
+        Ok((row.get_unwrap(0), phash.map(|v| v as u64)))
+    })
+}
+
+/// List of rowids, as a parameter of `rarray`.
+pub type Rowids = std::rc::Rc<Vec<SqlValue>>;
+
+/// Returns (rowid, file) of the files with given rowids, with their thumbnails nearest to the
+/// given size.
+pub fn files_by_rowids<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (u32, Rowids), (Rowid, crate::model::FileInfo)> {
+    let sql = concatcp!(
+        "SELECT rowid, hash, date, date_offset, date_source, media, ",
+        NEAREST_THUMBNAIL,
+        "
+FROM file
+WHERE rowid IN rarray(?2)"
+    );
+    TypedQuery::new(db, sql, |row| {
+        let rowid = row.get_unwrap(0);
+        let f = crate::model::FileInfo {
+            hash: row.get_unwrap(1),
+            date: row.get_unwrap(2),
+            date_offset: offset_from_sql(row.get_unwrap(3)),
+            date_source: row.get_unwrap(4),
+            media: row.get_unwrap(5),
+            thumb: row.get_unwrap(6),
+        };
+        Ok((rowid, f))
+    })
+}
+
+/// Returns (hash, marker root, relative path, thumbnail) of all locations of image files with
+/// thumbnails not yet corrected for orientation, in trees that were scanned at least once.
+pub fn unoriented_files<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (), (String, String, String, Vec<u8>)> {
+    let sql = concatcp!(
+        r"
+SELECT hash, root, path, thumbnail.jpeg
+FROM file
+JOIN location ON location.file_id = file.rowid
+JOIN marker_seen ON marker_seen.backend_tag = location.backend_tag
+JOIN thumbnail ON thumbnail.file_id = file.rowid AND thumbnail.size = ",
+        THUMBNAIL_SIZE,
+        r"
+WHERE orientation IS NULL AND media = 'image'
+ORDER BY hash"
+    );
+    TypedQuery::new(db, sql, |row| {
+        Ok((
+            row.get_unwrap(0),
+            row.get_unwrap(1),
+            row.get_unwrap(2),
+            row.get_unwrap(3),
+        ))
+    })
+}
+
+/// Links the first RAW file found at any of the `raws` paths with the first JPEG file found at any
+/// of the `jpegs` paths, all in the `marker` tree. If none is found on either side, does nothing.
+pub fn pair_raw_with_jpeg(
+    db: &Connection,
+    marker: &str,
+    raws: &[String],
+    jpegs: &[String],
+) -> Result<()> {
+    let to_array = |paths: &[String]| {
+        std::rc::Rc::new(
+            paths
+                .iter()
+                .cloned()
+                .map(SqlValue::from)
+                .collect::<Vec<_>>(),
+        )
+    };
+    db.execute(
+        "INSERT INTO raw_pair(raw_file_id, jpeg_file_id)
+            SELECT raw.file_id, jpeg.file_id
+              FROM location AS raw, location AS jpeg
+              WHERE raw.backend_tag = ?1 AND raw.path IN rarray(?2)
+              AND jpeg.backend_tag = ?1 AND jpeg.path IN rarray(?3)
+              LIMIT 1
+            ON CONFLICT(raw_file_id) DO UPDATE SET
+              jpeg_file_id = excluded.jpeg_file_id",
+        params![&marker, to_array(raws), to_array(jpegs)],
+    )?;
+    Ok(())
+}
+
+pub fn tags_for_file_ids<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (std::rc::Rc<Vec<SqlValue>>,), (String, bool, u32)> {
+    let sql = r"
+SELECT tag.name, tag.hidden, count(ttt)
+FROM tag LEFT JOIN (
+    SELECT tag_id AS ttt
+    FROM file_tag
+    WHERE file_id IN rarray(?)
+) ON tag.rowid = ttt
+GROUP BY tag.rowid";
+    TypedQuery::new(db, sql, |row| {
+        let name: String = row.get_unwrap(0);
+        let hidden: bool = row.get_unwrap(1);
+        // FIXME[LATER]: u32 or maybe i64 ?
+        let count: u32 = row.get_unwrap(2);
+        Ok((name, hidden, count))
+    })
+}
+
+// pub fn visible_files(db: &Connection, oal: OffsetAndLimit) -> impl Iterator<Item = anyhow::Result<Rowid>> + '_ {
+pub fn visible_files_rowids(db: &Connection, oal: OffsetAndLimit) -> Vec<Rowid> {
+    let mut query = visible_files_in_limit_and_offset(&db);
+    query
+        .run((THUMBNAIL_SIZE, oal.limit, oal.offset))
+        .map(|v| v.unwrap())
+        .map(|(rowid, _, _)| rowid)
+        .collect()
+}
+
+const FROM_VISIBLE_FILE: &str = r"
+FROM file
+WHERE rowid NOT IN (
+  SELECT file_id AS hidden_file
+  FROM file_tag
+  WHERE tag_id IN (
+    SELECT ROWID
+    FROM tag
+    WHERE hidden IS TRUE
+  )
+)
+AND rowid NOT IN (
+  SELECT raw_file_id
+  FROM raw_pair
+)
+AND NOT EXISTS (
+  SELECT 1
+  FROM area_filter AS f
+  WHERE NOT EXISTS (
+    SELECT 1
+    FROM file_meta AS m
+    WHERE m.file_id = file.rowid
+    AND m.lat BETWEEN f.south AND f.north
+    AND (
+      (f.west <= f.east AND m.lon BETWEEN f.west AND f.east)
+      OR (f.west > f.east AND (m.lon >= f.west OR m.lon <= f.east))
+    )
+    AND (
+      f.km IS NULL
+      OR ((m.lat - f.lat) * (m.lat - f.lat)
+        + (m.lon - f.lon) * f.lon_scale * (m.lon - f.lon) * f.lon_scale)
+        * 111.195 * 111.195 <= f.km * f.km
+    )
+  )
+)
+AND NOT EXISTS (
+  SELECT 1
+  FROM place_filter AS f
+  WHERE NOT EXISTS (
+    SELECT 1
+    FROM file_meta AS m
+    WHERE m.file_id = file.rowid
+    AND (f.country IS NULL OR m.country = f.country COLLATE UNICASE)
+    AND (f.region IS NULL OR m.region = f.region COLLATE UNICASE)
+    AND (f.city IS NULL OR m.city = f.city COLLATE UNICASE)
+  )
+)
+AND NOT EXISTS (
+  SELECT 1
+  FROM camera_filter AS f
+  WHERE NOT EXISTS (
+    SELECT 1
+    FROM file_meta AS m
+    WHERE m.file_id = file.rowid
+    AND (f.make IS NULL OR m.make = f.make COLLATE UNICASE)
+    AND (f.model IS NULL OR m.model = f.model COLLATE UNICASE)
+    AND (f.lens IS NULL OR m.lens = f.lens COLLATE UNICASE)
+    AND (f.min_focal_length IS NULL OR m.focal_length >= f.min_focal_length)
+    AND (f.max_focal_length IS NULL OR m.focal_length <= f.max_focal_length)
+  )
+)
+ORDER BY instant
+";
+
+/// The thumbnail of a file nearest to the size given as the first parameter, preferring bigger
+/// ones, which only need scaling down; empty if the file has none yet. Followed by its size.
+const NEAREST_THUMBNAIL: &str = "
+  IFNULL((SELECT jpeg FROM thumbnail WHERE file_id = file.rowid
+    ORDER BY size < ?1, abs(size - ?1) LIMIT 1), x''),
+  (SELECT size FROM thumbnail WHERE file_id = file.rowid
+    ORDER BY size < ?1, abs(size - ?1) LIMIT 1)";
+
+/// Rowid of a file, the file, and the size of its thumbnail, if it has any.
+pub type SizedFile = (Rowid, crate::model::FileInfo, Option<u32>);
+
+/// Returns the visible files, with their thumbnails nearest to the given size (see:
+/// [NEAREST_THUMBNAIL]).
+pub fn visible_files_in_limit_and_offset<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (u32, i64, i64), SizedFile> {
+    let sql = concatcp!(
+        "SELECT rowid, hash, date, date_offset, date_source, media, ",
+        NEAREST_THUMBNAIL,
+        FROM_VISIBLE_FILE,
+        "LIMIT ? OFFSET ?"
+    );
+    TypedQuery::new(db, sql, |row| {
+        let rowid = row.get_unwrap(0);
+        let f = crate::model::FileInfo {
+            hash: row.get_unwrap(1),
+            date: row.get_unwrap(2),
+            date_offset: offset_from_sql(row.get_unwrap(3)),
+            date_source: row.get_unwrap(4),
+            media: row.get_unwrap(5),
+            thumb: row.get_unwrap(6),
+        };
+        Ok((rowid, f, row.get_unwrap(7)))
+    })
+}
+
+fn offset_from_sql(seconds: Option<i32>) -> Option<FixedOffset> {
+    seconds.and_then(FixedOffset::east_opt)
+}
+
+pub fn locations_of_file_at_offset<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (i64,), (String, String)> {
+    let sql = concatcp!(
+        r"
+SELECT backend_tag, path
+FROM location
+WHERE file_id = (SELECT file.rowid",
+        FROM_VISIBLE_FILE,
+        r"LIMIT 1 OFFSET ?)
+ORDER BY backend_tag ASC, path ASC",
+    );
+    TypedQuery::new(db, sql, |row| {
+        let backend: String = row.get_unwrap(0);
+        let path: String = row.get_unwrap(1);
+        Ok((backend, path))
+    })
+}
+
+/// Coordinates of the file at offset in the gallery, if known.
+pub fn gps_of_file_at_offset<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (i64,), (f64, f64, Option<f64>)> {
+    let sql = concatcp!(
+        r"
+SELECT lat, lon, alt
+FROM file_meta
+WHERE lat IS NOT NULL AND lon IS NOT NULL
+AND file_id = (SELECT file.rowid",
+        FROM_VISIBLE_FILE,
+        r"LIMIT 1 OFFSET ?)",
+    );
+    TypedQuery::new(db, sql, |row| {
+        Ok((row.get_unwrap(0), row.get_unwrap(1), row.get_unwrap(2)))
+    })
+}
+
+pub fn set_gps(db: &Connection, hash: &str, gps: Option<&GpsPosition>) -> Result<()> {
+    db.execute(
+        "INSERT INTO file_meta(file_id,lat,lon,alt)
+            SELECT rowid, ?, ?, ? FROM file
+              WHERE hash = ?
+            ON CONFLICT(file_id) DO UPDATE SET
+              lat = excluded.lat,
+              lon = excluded.lon,
+              alt = excluded.alt",
+        params![
+            gps.map(|g| g.lat),
+            gps.map(|g| g.lon),
+            gps.and_then(|g| g.alt),
+            &hash
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn set_place(db: &Connection, hash: &str, place: Option<&Place>) -> Result<()> {
+    db.execute(
+        "UPDATE file_meta
+            SET country = ?, region = ?, city = ?
+            WHERE file_id = (SELECT rowid FROM file WHERE hash = ?)",
+        params![
+            place.map(|p| &p.country),
+            place.and_then(|p| p.region.as_ref()),
+            place.map(|p| &p.city),
+            &hash
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn set_camera(db: &Connection, hash: &str, camera: &Camera) -> Result<()> {
+    db.execute(
+        "INSERT INTO file_meta(file_id,make,model,lens,focal_length,iso,exposure,width,height)
+            SELECT rowid, ?, ?, ?, ?, ?, ?, ?, ? FROM file
+              WHERE hash = ?
+            ON CONFLICT(file_id) DO UPDATE SET
+              make = excluded.make,
+              model = excluded.model,
+              lens = excluded.lens,
+              focal_length = excluded.focal_length,
+              iso = excluded.iso,
+              exposure = excluded.exposure,
+              width = excluded.width,
+              height = excluded.height",
+        params![
+            &camera.make,
+            &camera.model,
+            &camera.lens,
+            camera.focal_length,
+            camera.iso,
+            camera.exposure,
+            camera.width,
+            camera.height,
+            &hash
+        ],
+    )?;
+    Ok(())
+}
+
+/// Returns (make, model, number of files) of all cameras which took the files.
+pub fn cameras<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (), (Option<String>, Option<String>, u32)> {
+    let sql = r"
+SELECT make, model, COUNT(*)
+FROM file_meta
+WHERE make IS NOT NULL OR model IS NOT NULL
+GROUP BY make, model
+ORDER BY make ASC, model ASC";
+    TypedQuery::new(db, sql, |row| {
+        Ok((row.get_unwrap(0), row.get_unwrap(1), row.get_unwrap(2)))
+    })
+}
+
+/// Returns (lens, number of files) of all lenses with which the files were taken.
+pub fn lenses<'cnx>(db: &'cnx Connection) -> TypedQuery<'cnx, (), (String, u32)> {
+    let sql = r"
+SELECT lens, COUNT(*)
+FROM file_meta
+WHERE lens IS NOT NULL
+GROUP BY lens
+ORDER BY lens ASC";
+    TypedQuery::new(db, sql, |row| Ok((row.get_unwrap(0), row.get_unwrap(1))))
+}
+
+/// Returns (focal length in millimeters, number of files) of all focal lengths at which the files
+/// were taken.
+pub fn focal_lengths<'cnx>(db: &'cnx Connection) -> TypedQuery<'cnx, (), (f64, u32)> {
+    let sql = r"
+SELECT focal_length, COUNT(*)
+FROM file_meta
+WHERE focal_length IS NOT NULL
+GROUP BY focal_length
+ORDER BY focal_length ASC";
+    TypedQuery::new(db, sql, |row| Ok((row.get_unwrap(0), row.get_unwrap(1))))
+}
+
+/// Returns (hash, latitude, longitude) of all files with known coordinates.
+pub fn geotagged_files<'cnx>(db: &'cnx Connection) -> TypedQuery<'cnx, (), (String, f64, f64)> {
+    let sql = r"
+SELECT hash, lat, lon
+FROM file_meta
+JOIN file ON file.rowid = file_id
+WHERE lat IS NOT NULL AND lon IS NOT NULL";
+    TypedQuery::new(db, sql, |row| {
+        Ok((row.get_unwrap(0), row.get_unwrap(1), row.get_unwrap(2)))
+    })
+}
+
+/// Returns (country, region, city, number of files) of all places where files were created.
+pub fn places<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (), (String, Option<String>, String, u32)> {
+    let sql = r"
+SELECT country, region, city, COUNT(*)
+FROM file_meta
+WHERE country IS NOT NULL AND city IS NOT NULL
+GROUP BY country, region, city
+ORDER BY country ASC, region ASC, city ASC";
+    TypedQuery::new(db, sql, |row| {
+        Ok((
+            row.get_unwrap(0),
+            row.get_unwrap(1),
+            row.get_unwrap(2),
+            row.get_unwrap(3),
+        ))
+    })
+}
+
+/// Place where files shown in the gallery must have been created; fields set to `None` match any
+/// value. Names are compared case-insensitively.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlaceFilter {
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+}
+
+/// Limits the files listed by the gallery queries to ones created in the `place`, or lifts the
+/// limit if `None`. The setting is kept per connection.
+pub fn set_place_filter(db: &Connection, place: Option<&PlaceFilter>) -> Result<()> {
+    db.execute("DELETE FROM place_filter", [])?;
+    if let Some(place) = place {
+        db.execute(
+            "INSERT INTO place_filter(country,region,city) VALUES(?,?,?)",
+            params![&place.country, &place.region, &place.city],
+        )?;
+    }
+    Ok(())
+}
+
+/// Camera and lens with which files shown in the gallery must have been taken; fields set to
+/// `None` match any value. Names are compared case-insensitively, focal lengths (in millimeters)
+/// are an inclusive range.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraFilter {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    pub min_focal_length: Option<f64>,
+    pub max_focal_length: Option<f64>,
+}
+
+/// Limits the files listed by the gallery queries to ones taken with the `camera`, or lifts the
+/// limit if `None`. The setting is kept per connection.
+pub fn set_camera_filter(db: &Connection, camera: Option<&CameraFilter>) -> Result<()> {
+    db.execute("DELETE FROM camera_filter", [])?;
+    if let Some(camera) = camera {
+        db.execute(
+            "INSERT INTO camera_filter(make,model,lens,min_focal_length,max_focal_length)
+                VALUES(?,?,?,?,?)",
+            params![
+                &camera.make,
+                &camera.model,
+                &camera.lens,
+                camera.min_focal_length,
+                camera.max_focal_length
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Area in which files shown in the gallery must have been taken.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AreaFilter {
+    /// Coordinates in degrees. If `west` is greater than `east`, the box crosses the 180th
+    /// meridian.
+    BoundingBox {
+        south: f64,
+        west: f64,
+        north: f64,
+        east: f64,
+    },
+    /// Within `km` kilometers from a point. Distances are approximated as on a flat map, which is
+    /// precise enough for radii up to a few hundred kilometers.
+    // TODO[LATER]: support radius filters crossing the 180th meridian
+    Radius { lat: f64, lon: f64, km: f64 },
+}
+
+/// Limits the files listed by the gallery queries to ones located in the `area`, or lifts the
+/// limit if `None`. The setting is kept per connection.
+pub fn set_area_filter(db: &Connection, area: Option<&AreaFilter>) -> Result<()> {
+    const KM_PER_DEGREE: f64 = 111.195;
+    db.execute("DELETE FROM area_filter", [])?;
+    match area {
+        None => {}
+        Some(&AreaFilter::BoundingBox {
+            south,
+            west,
+            north,
+            east,
+        }) => {
+            db.execute(
+                "INSERT INTO area_filter(south,west,north,east) VALUES(?,?,?,?)",
+                params![south, west, north, east],
+            )?;
+        }
+        Some(&AreaFilter::Radius { lat, lon, km }) => {
+            let lon_scale = lat.to_radians().cos();
+            let d_lat = km / KM_PER_DEGREE;
+            // Close to a pole, any longitude may be within the radius.
+            let d_lon = if lon_scale > 1e-6 {
+                (km / KM_PER_DEGREE / lon_scale).min(180.0)
+            } else {
+                180.0
+            };
+            db.execute(
+                "INSERT INTO area_filter(south,west,north,east,lat,lon,km,lon_scale)
+                    VALUES(?,?,?,?,?,?,?,?)",
+                params![
+                    lat - d_lat,
+                    (lon - d_lon).max(-180.0),
+                    lat + d_lat,
+                    (lon + d_lon).min(180.0),
+                    lat,
+                    lon,
+                    km,
+                    lon_scale
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn n_files(db: &Connection) -> u32 {
+    db.query_row("SELECT COUNT(*) FROM file", [], |row| row.get(0))
+        .unwrap()
+}
+
+pub fn remove(db: &Connection, marker: &str, relative: &str) -> Result<()> {
+    db.execute(
+        "DELETE FROM location
+            WHERE backend_tag = ?
+            AND path = ?",
+        params![&marker, &relative],
+    )?;
+    clear_scrub_mismatch(db, marker, relative)
+}
+
+pub fn set_alias_of(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+    alias_of: Option<&str>,
+) -> Result<()> {
+    db.execute(
+        "UPDATE location
+            SET alias_of = ?
+            WHERE backend_tag = ?
+            AND path = ?",
+        params![&alias_of, &marker, &relative],
+    )?;
+    Ok(())
+}
+
+pub fn skip(db: &Connection, marker: &str, relative: &str, reason: &str) -> Result<()> {
+    db.execute(
+        "INSERT INTO skipped(backend_tag,path,reason) VALUES(?,?,?)
+            ON CONFLICT(backend_tag, path) DO UPDATE SET
+              reason = excluded.reason",
+        params![&marker, &relative, &reason],
+    )?;
+    Ok(())
+}
+
+pub fn clear_skipped(db: &Connection, marker: &str) -> Result<()> {
+    db.execute(
+        "DELETE FROM skipped
+            WHERE backend_tag = ?",
+        params![&marker],
+    )?;
+    Ok(())
+}
+
+/// Returns (marker, path, reason) of all files skipped in latest scans.
+pub fn skipped<'cnx>(db: &'cnx Connection) -> TypedQuery<'cnx, (), (String, String, String)> {
+    let sql = r"
+SELECT backend_tag, path, reason
+FROM skipped
+ORDER BY backend_tag ASC, path ASC";
+    TypedQuery::new(db, sql, |row| {
+        Ok((row.get_unwrap(0), row.get_unwrap(1), row.get_unwrap(2)))
+    })
+}
+
+/// Records that the file at `relative` path (empty for the tree's root) could not be cataloged.
+pub fn add_scan_problem(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+    kind: ProblemKind,
+    message: &str,
 ) -> Result<()> {
     db.execute(
-        "INSERT INTO file(hash,date,thumbnail) VALUES(?,?,?)
-            ON CONFLICT(hash) DO UPDATE SET
-                date = ifnull(date, excluded.date),
-                thumbnail = excluded.thumbnail",
-        params![&info.hash, &info.date, &info.thumb],
+        "INSERT INTO scan_problem(backend_tag,path,kind,message) VALUES(?,?,?,?)
+            ON CONFLICT(backend_tag, path) DO UPDATE SET
+              kind = excluded.kind,
+              message = excluded.message",
+        params![&marker, &relative, &kind, &message],
     )?;
+    Ok(())
+}
+
+pub fn clear_scan_problems(db: &Connection, marker: &str) -> Result<()> {
     db.execute(
-        "INSERT INTO location(file_id,backend_tag,path)
-            SELECT rowid, ?, ? FROM file
-              WHERE hash = ? LIMIT 1
-            ON CONFLICT(backend_tag, path) DO UPDATE SET
-              file_id = excluded.file_id",
-        params![&marker, &relative, &info.hash],
+        "DELETE FROM scan_problem
+            WHERE backend_tag = ?",
+        params![&marker],
     )?;
     Ok(())
 }
 
-pub fn tags_for_file_ids<'cnx>(
+/// Returns (marker, path, kind, message) of all files which could not be cataloged in latest
+/// scans.
+pub fn scan_problems<'cnx>(
     db: &'cnx Connection,
-) -> TypedQuery<'cnx, (std::rc::Rc<Vec<SqlValue>>,), (String, bool, u32)> {
+) -> TypedQuery<'cnx, (), (String, String, ProblemKind, String)> {
     let sql = r"
-SELECT tag.name, tag.hidden, count(ttt)
-FROM tag LEFT JOIN (
-    SELECT tag_id AS ttt
-    FROM file_tag
-    WHERE file_id IN rarray(?)
-) ON tag.rowid = ttt
-GROUP BY tag.rowid";
+SELECT backend_tag, path, kind, message
+FROM scan_problem
+ORDER BY backend_tag ASC, path ASC";
     TypedQuery::new(db, sql, |row| {
-        let name: String = row.get_unwrap(0);
-        let hidden: bool = row.get_unwrap(1);
-        // FIXME[LATER]: u32 or maybe i64 ?
-        let count: u32 = row.get_unwrap(2);
-        Ok((name, hidden, count))
+        Ok((
+            row.get_unwrap(0),
+            row.get_unwrap(1),
+            row.get_unwrap(2),
+            row.get_unwrap(3),
+        ))
     })
 }
 
-// pub fn visible_files(db: &Connection, oal: OffsetAndLimit) -> impl Iterator<Item = anyhow::Result<Rowid>> + '_ {
-pub fn visible_files_rowids(db: &Connection, oal: OffsetAndLimit) -> Vec<Rowid> {
-    let mut query = visible_files_in_limit_and_offset(&db);
-    query
-        .run((oal.limit, oal.offset))
-        .map(|v| v.unwrap())
-        .map(|(rowid, _)| rowid)
-        .collect()
+/// Returns (started, target) of the scrub of the `marker` which is not finished yet, if any.
+pub fn scrub_run(db: &Connection, marker: &str) -> Result<Option<(NaiveDateTime, u64)>> {
+    let run = db
+        .query_row(
+            "SELECT started, target FROM scrub_run
+                WHERE backend_tag = ?",
+            params![&marker],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(run)
 }
 
-const FROM_VISIBLE_FILE: &str = r"
-FROM file
-WHERE rowid NOT IN (
-  SELECT file_id AS hidden_file
-  FROM file_tag
-  WHERE tag_id IN (
-    SELECT ROWID
-    FROM tag
-    WHERE hidden IS TRUE
-  )
-)
-ORDER BY date
-";
+/// Records the start of a scrub of the `marker`, which is to verify `target` locations.
+pub fn start_scrub(
+    db: &Connection,
+    marker: &str,
+    started: NaiveDateTime,
+    target: u64,
+) -> Result<()> {
+    db.execute(
+        "INSERT INTO scrub_run(backend_tag,started,target) VALUES(?,?,?)
+            ON CONFLICT(backend_tag) DO UPDATE SET
+              started = excluded.started,
+              target = excluded.target",
+        params![&marker, &started, &target],
+    )?;
+    Ok(())
+}
 
-pub fn visible_files_in_limit_and_offset<'cnx>(
-    db: &'cnx Connection,
-) -> TypedQuery<'cnx, (i64, i64), (i64, crate::model::FileInfo)> {
-    let sql = concatcp!(
-        "SELECT rowid, hash, date, thumbnail",
-        FROM_VISIBLE_FILE,
-        "LIMIT ? OFFSET ?"
-    );
-    TypedQuery::new(db, sql, |row| {
-        let rowid = row.get_unwrap(0);
-        let f = crate::model::FileInfo {
-            hash: row.get_unwrap(1),
-            date: row.get_unwrap(2),
-            thumb: row.get_unwrap(3),
-        };
-        Ok((rowid, f))
-    })
+pub fn finish_scrub(db: &Connection, marker: &str) -> Result<()> {
+    db.execute(
+        "DELETE FROM scrub_run
+            WHERE backend_tag = ?",
+        params![&marker],
+    )?;
+    Ok(())
 }
 
-pub fn locations_of_file_at_offset<'cnx>(
-    db: &'cnx Connection,
-) -> TypedQuery<'cnx, (i64,), (String, String)> {
-    let sql = concatcp!(
-        r"
-SELECT backend_tag, path
-FROM location
-WHERE file_id = (SELECT file.rowid",
-        FROM_VISIBLE_FILE,
-        r"LIMIT 1 OFFSET ?)
-ORDER BY backend_tag ASC, path ASC",
-    );
-    TypedQuery::new(db, sql, |row| {
-        let backend: String = row.get_unwrap(0);
-        let path: String = row.get_unwrap(1);
-        Ok((backend, path))
-    })
+/// Number of locations of the `marker` to scrub; symlinks are left out, as their targets are
+/// verified anyway.
+pub fn n_scrubbable(db: &Connection, marker: &str) -> Result<u64> {
+    let n = db.query_row(
+        "SELECT COUNT(*) FROM location
+            WHERE backend_tag = ?
+            AND alias_of IS NULL",
+        params![&marker],
+        |row| row.get(0),
+    )?;
+    Ok(n)
 }
 
-pub fn n_files(db: &Connection) -> u32 {
-    db.query_row("SELECT COUNT(*) FROM file", [], |row| row.get(0))
-        .unwrap()
+/// Number of locations of the `marker` scrubbed since `since`.
+pub fn n_scrubbed_since(db: &Connection, marker: &str, since: NaiveDateTime) -> Result<u64> {
+    let n = db.query_row(
+        "SELECT COUNT(*) FROM location
+            WHERE backend_tag = ?
+            AND scrubbed >= ?",
+        params![&marker, &since],
+        |row| row.get(0),
+    )?;
+    Ok(n)
 }
 
-pub fn remove(db: &Connection, marker: &str, relative: &str) -> Result<()> {
+/// Returns (path, hash) of up to `limit` locations of the `marker` not scrubbed since `since`,
+/// starting with the least recently scrubbed ones (never scrubbed first).
+pub fn least_recently_scrubbed(
+    db: &Connection,
+    marker: &str,
+    since: NaiveDateTime,
+    limit: u64,
+) -> Result<Vec<(String, String)>> {
+    let mut stmt = db.prepare(
+        "SELECT path, hash FROM location
+            JOIN file ON location.file_id = file.rowid
+            WHERE backend_tag = ?
+            AND alias_of IS NULL
+            AND (scrubbed IS NULL OR scrubbed < ?)
+            ORDER BY scrubbed ASC, path ASC
+            LIMIT ?",
+    )?;
+    let locations = stmt
+        .query_map(params![&marker, &since, &limit], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(locations)
+}
+
+/// Records that contents at the location were read and compared with the digest by a scrub.
+pub fn set_verified(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+    verified: NaiveDateTime,
+) -> Result<()> {
     db.execute(
-        "DELETE FROM location
+        "UPDATE location
+            SET verified = ?1, scrubbed = ?1
+            WHERE backend_tag = ?2
+            AND path = ?3",
+        params![&verified, &marker, &relative],
+    )?;
+    Ok(())
+}
+
+/// Records that a scrub got to the location, but could not read it.
+pub fn set_scrubbed(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+    scrubbed: NaiveDateTime,
+) -> Result<()> {
+    db.execute(
+        "UPDATE location
+            SET scrubbed = ?
+            WHERE backend_tag = ?
+            AND path = ?",
+        params![&scrubbed, &marker, &relative],
+    )?;
+    Ok(())
+}
+
+pub fn add_scrub_mismatch(
+    db: &Connection,
+    marker: &str,
+    relative: &str,
+    algorithm: HashAlgorithm,
+    expected: &str,
+    actual: &str,
+    found: NaiveDateTime,
+) -> Result<()> {
+    db.execute(
+        "INSERT INTO scrub_mismatch(backend_tag,path,algorithm,expected,actual,found)
+            VALUES(?,?,?,?,?,?)
+            ON CONFLICT(backend_tag, path) DO UPDATE SET
+              algorithm = excluded.algorithm,
+              expected = excluded.expected,
+              actual = excluded.actual,
+              found = excluded.found",
+        params![&marker, &relative, &algorithm, &expected, &actual, &found],
+    )?;
+    Ok(())
+}
+
+/// Forgets an earlier mismatch at the location, e.g. after the file was restored.
+pub fn clear_scrub_mismatch(db: &Connection, marker: &str, relative: &str) -> Result<()> {
+    db.execute(
+        "DELETE FROM scrub_mismatch
             WHERE backend_tag = ?
             AND path = ?",
         params![&marker, &relative],
@@ -209,6 +1543,26 @@ pub fn remove(db: &Connection, marker: &str, relative: &str) -> Result<()> {
     Ok(())
 }
 
+/// Returns (marker, path, algorithm, expected, actual) of all locations found mismatching their
+/// digest by scrubs.
+pub fn scrub_mismatches<'cnx>(
+    db: &'cnx Connection,
+) -> TypedQuery<'cnx, (), (String, String, HashAlgorithm, String, String)> {
+    let sql = r"
+SELECT backend_tag, path, algorithm, expected, actual
+FROM scrub_mismatch
+ORDER BY backend_tag ASC, path ASC";
+    TypedQuery::new(db, sql, |row| {
+        Ok((
+            row.get_unwrap(0),
+            row.get_unwrap(1),
+            row.get_unwrap(2),
+            row.get_unwrap(3),
+            row.get_unwrap(4),
+        ))
+    })
+}
+
 pub fn set_tag_hidden(db: &SyncedDb, name: &str, hidden: bool) {
     // TODO[LATER]: avoid unwrap?
     let db = db.lock().unwrap();
@@ -260,20 +1614,30 @@ impl Iterator for LooseIterator {
 
 #[cfg(test)]
 mod test {
-    use chrono::NaiveDate;
+    use chrono::{FixedOffset, NaiveDate, NaiveDateTime};
+    use const_format::concatcp;
     use std::rc::Rc;
 
-    use crate::model::FileInfo;
+    use crate::imaging::THUMBNAIL_SIZE;
+    use crate::model::{Camera, DateSource, FileInfo, GpsPosition, MediaType, Place};
     use crate::{db, db::SqlValue};
 
     fn all_files(conn: &db::Connection) -> Vec<FileInfo> {
-        conn.prepare("SELECT hash, date, thumbnail FROM file")
+        let sql = concatcp!(
+            "SELECT hash, date, date_offset, date_source, media, ",
+            db::NEAREST_THUMBNAIL,
+            " FROM file"
+        );
+        conn.prepare(sql)
             .unwrap()
-            .query_map([], |row| {
+            .query_map([THUMBNAIL_SIZE], |row| {
                 Ok(FileInfo {
                     hash: row.get_unwrap(0),
                     date: row.get_unwrap(1),
-                    thumb: row.get_unwrap(2),
+                    date_offset: db::offset_from_sql(row.get_unwrap(2)),
+                    date_source: row.get_unwrap(3),
+                    media: row.get_unwrap(4),
+                    thumb: row.get_unwrap(5),
                 })
             })
             .unwrap()
@@ -281,6 +1645,21 @@ mod test {
             .collect()
     }
 
+    /// Sorted hashes of the files visible in the gallery once `set_filter` sets the `filter`.
+    fn visible_with<F>(
+        conn: &db::Connection,
+        set_filter: fn(&db::Connection, Option<&F>) -> anyhow::Result<()>,
+        filter: Option<F>,
+    ) -> Vec<String> {
+        set_filter(conn, filter.as_ref()).unwrap();
+        let mut hashes = db::visible_files_in_limit_and_offset(conn)
+            .run((THUMBNAIL_SIZE, 10, 0))
+            .map(|v| v.unwrap().1.hash)
+            .collect::<Vec<_>>();
+        hashes.sort();
+        hashes
+    }
+
     #[test]
     fn rusqlite_feat_array() {
         let conn = rusqlite::Connection::open_in_memory().unwrap();
@@ -302,6 +1681,71 @@ mod test {
         assert_eq!(raw_vals, &got[..]);
     }
 
+    #[test]
+    fn failed_migration_rolled_back() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let migrations = [
+            "CREATE TABLE foo (x INTEGER)",
+            "ALTER TABLE foo ADD COLUMN y INTEGER;
+             ALTER TABLE missing ADD COLUMN z INTEGER",
+        ];
+        assert!(db::apply_migrations(&conn, &migrations).is_err());
+        assert!(conn.is_autocommit());
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 1);
+        // The half of the failed migration which succeeded is undone too.
+        assert!(conn.prepare("SELECT y FROM foo").is_err());
+    }
+
+    #[test]
+    fn init_twice() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        db::init(&conn).unwrap();
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, db::MIGRATIONS.len());
+    }
+
+    #[test]
+    fn raw_paired_with_jpeg_is_not_visible() {
+        let marker: &str = "foo-marker";
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        for (path, hash) in [
+            ("a.CR2", "raw-hash"),
+            ("a.jpg", "jpeg-hash"),
+            ("b.nef", "lone-raw"),
+        ] {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                date: None,
+                date_offset: None,
+                date_source: None,
+                thumb: Vec::new(),
+                media: MediaType::Image,
+            };
+            db::upsert(&conn, marker, path, &info).unwrap();
+        }
+
+        let raws = vec!["a.CR2".to_string()];
+        let jpegs = vec!["a.jpeg".to_string(), "a.jpg".to_string()];
+        db::pair_raw_with_jpeg(&conn, marker, &raws, &jpegs).unwrap();
+        let lone_raws = vec!["b.nef".to_string()];
+        let lone_jpegs = vec!["b.jpg".to_string()];
+        db::pair_raw_with_jpeg(&conn, marker, &lone_raws, &lone_jpegs).unwrap();
+
+        let mut visible = db::visible_files_in_limit_and_offset(&conn)
+            .run((THUMBNAIL_SIZE, 10, 0))
+            .map(|v| v.unwrap().1.hash)
+            .collect::<Vec<_>>();
+        visible.sort();
+        assert_eq!(visible, vec!["jpeg-hash", "lone-raw"]);
+    }
+
     #[test]
     fn upsert_changed_hash_at_location() {
         // arrange
@@ -321,7 +1765,10 @@ mod test {
             &FileInfo {
                 hash: hash_a.clone(),
                 date: None,
+                date_offset: None,
+                date_source: None,
                 thumb: vec![b'A'],
+                media: MediaType::Image,
             },
         )
         .unwrap();
@@ -331,7 +1778,10 @@ mod test {
             vec![FileInfo {
                 hash: hash_a.clone(),
                 date: None,
-                thumb: vec![b'A']
+                date_offset: None,
+                date_source: None,
+                thumb: vec![b'A'],
+                media: MediaType::Image,
             }]
         );
 
@@ -344,7 +1794,10 @@ mod test {
             &FileInfo {
                 hash: hash_b.clone(),
                 date: Some(date_2),
+                date_offset: None,
+                date_source: Some(DateSource::Exif),
                 thumb: vec![b'B'],
+                media: MediaType::Image,
             },
         )
         .unwrap();
@@ -358,17 +1811,337 @@ mod test {
                 FileInfo {
                     hash: hash_a,
                     date: None,
-                    thumb: vec![b'A']
+                    date_offset: None,
+                    date_source: None,
+                    thumb: vec![b'A'],
+                    media: MediaType::Image,
                 },
                 FileInfo {
                     hash: hash_b,
                     date: Some(date_2),
-                    thumb: vec![b'B']
+                    date_offset: None,
+                    date_source: Some(DateSource::Exif),
+                    thumb: vec![b'B'],
+                    media: MediaType::Image,
                 },
             ]
         );
     }
 
+    #[test]
+    fn visible_files_ordered_by_instant() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let at = |h| Some(NaiveDate::from_ymd(2021, 3, 4).and_hms(h, 0, 0));
+        for (hash, date, offset) in [
+            ("tokyo-noon", at(12), FixedOffset::east_opt(9 * 3600)),
+            ("lisbon-morning", at(8), FixedOffset::east_opt(0)),
+            ("unknown-9am", at(9), None),
+        ] {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                date,
+                date_offset: offset,
+                date_source: Some(DateSource::Exif),
+                thumb: Vec::new(),
+                media: MediaType::Image,
+            };
+            db::upsert(&conn, "foo-marker", hash, &info).unwrap();
+        }
+
+        let visible = db::visible_files_in_limit_and_offset(&conn)
+            .run((THUMBNAIL_SIZE, 10, 0))
+            .map(|v| v.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            visible.iter().map(|f| f.hash.as_str()).collect::<Vec<_>>(),
+            vec!["tokyo-noon", "lisbon-morning", "unknown-9am"]
+        );
+        assert_eq!(visible[0].date, at(12));
+        assert_eq!(visible[0].date_offset, FixedOffset::east_opt(9 * 3600));
+    }
+
+    #[test]
+    fn area_filters() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        for (hash, gps) in [
+            ("krakow", Some((50.0614, 19.9366))),
+            ("wieliczka", Some((49.9833, 20.0556))),
+            ("warsaw", Some((52.2297, 21.0122))),
+            ("fiji", Some((-17.7134, 178.0650))),
+            ("no-gps", None),
+        ] {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                date: None,
+                date_offset: None,
+                date_source: None,
+                thumb: Vec::new(),
+                media: MediaType::Image,
+            };
+            db::upsert(&conn, "foo-marker", hash, &info).unwrap();
+            let gps = gps.map(|(lat, lon)| GpsPosition {
+                lat,
+                lon,
+                alt: None,
+            });
+            db::set_gps(&conn, hash, gps.as_ref()).unwrap();
+        }
+        let visible = |area| visible_with(&conn, db::set_area_filter, area);
+
+        assert_eq!(
+            visible(Some(db::AreaFilter::Radius {
+                lat: 50.0614,
+                lon: 19.9366,
+                km: 15.0,
+            })),
+            vec!["krakow", "wieliczka"]
+        );
+        assert_eq!(
+            visible(Some(db::AreaFilter::Radius {
+                lat: 50.0614,
+                lon: 19.9366,
+                km: 5.0,
+            })),
+            vec!["krakow"]
+        );
+        assert_eq!(
+            visible(Some(db::AreaFilter::BoundingBox {
+                south: 50.0,
+                west: 14.0,
+                north: 55.0,
+                east: 24.0,
+            })),
+            vec!["krakow", "warsaw"]
+        );
+        assert_eq!(
+            visible(Some(db::AreaFilter::BoundingBox {
+                south: -20.0,
+                west: 170.0,
+                north: -15.0,
+                east: -170.0,
+            })),
+            vec!["fiji"]
+        );
+        assert_eq!(visible(None).len(), 5);
+        assert_eq!(
+            db::gps_of_file_at_offset(&conn)
+                .run((0,))
+                .map(|v| v.unwrap())
+                .collect::<Vec<_>>()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn place_facets_and_filter() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let place = |country: &str, region: Option<&str>, city: &str| Place {
+            country: country.to_string(),
+            region: region.map(str::to_string),
+            city: city.to_string(),
+        };
+        for (hash, place) in [
+            ("a", Some(place("PL", Some("Lesser Poland"), "Kraków"))),
+            ("b", Some(place("PL", Some("Lesser Poland"), "Kraków"))),
+            ("c", Some(place("PL", Some("Masovia"), "Warsaw"))),
+            ("d", Some(place("FJ", None, "Levuka"))),
+            ("e", None),
+            ("f", Some(place("PL", Some("Łódź Voivodeship"), "Łódź"))),
+        ] {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                date: None,
+                date_offset: None,
+                date_source: None,
+                thumb: Vec::new(),
+                media: MediaType::Image,
+            };
+            db::upsert(&conn, "foo-marker", hash, &info).unwrap();
+            let gps = GpsPosition {
+                lat: 0.0,
+                lon: 0.0,
+                alt: None,
+            };
+            db::set_gps(&conn, hash, Some(&gps)).unwrap();
+            db::set_place(&conn, hash, place.as_ref()).unwrap();
+        }
+
+        assert_eq!(
+            db::places(&conn)
+                .run(())
+                .map(|v| v.unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                ("FJ".to_string(), None, "Levuka".to_string(), 1),
+                (
+                    "PL".to_string(),
+                    Some("Lesser Poland".to_string()),
+                    "Kraków".to_string(),
+                    2
+                ),
+                (
+                    "PL".to_string(),
+                    Some("Masovia".to_string()),
+                    "Warsaw".to_string(),
+                    1
+                ),
+                (
+                    "PL".to_string(),
+                    Some("Łódź Voivodeship".to_string()),
+                    "Łódź".to_string(),
+                    1
+                ),
+            ]
+        );
+        assert_eq!(db::geotagged_files(&conn).run(()).count(), 6);
+
+        let visible = |filter| visible_with(&conn, db::set_place_filter, filter);
+        assert_eq!(
+            visible(Some(db::PlaceFilter {
+                city: Some("kraków".to_string()),
+                ..Default::default()
+            })),
+            vec!["a", "b"]
+        );
+        // Case of non-ASCII letters is folded too.
+        assert_eq!(
+            visible(Some(db::PlaceFilter {
+                city: Some("KRAKÓW".to_string()),
+                ..Default::default()
+            })),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            visible(Some(db::PlaceFilter {
+                region: Some("łódź voivodeship".to_string()),
+                city: Some("ŁÓDŹ".to_string()),
+                ..Default::default()
+            })),
+            vec!["f"]
+        );
+        assert_eq!(
+            visible(Some(db::PlaceFilter {
+                country: Some("PL".to_string()),
+                ..Default::default()
+            })),
+            vec!["a", "b", "c", "f"]
+        );
+        assert_eq!(visible(None).len(), 6);
+    }
+
+    #[test]
+    fn camera_facets_and_filter() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let camera = |make: &str, model: &str, lens: Option<&str>, focal_length| Camera {
+            make: Some(make.to_string()),
+            model: Some(model.to_string()),
+            lens: lens.map(str::to_string),
+            focal_length: Some(focal_length),
+            ..Default::default()
+        };
+        for (hash, camera) in [
+            (
+                "a",
+                camera("Canon", "EOS 80D", Some("EF50mm f/1.8 STM"), 50.0),
+            ),
+            ("b", camera("Canon", "EOS 80D", Some("EF-S18-55mm"), 24.0)),
+            ("c", camera("SAMSUNG", "GT-I9100", None, 4.0)),
+            ("d", Camera::default()),
+        ] {
+            let info = FileInfo {
+                hash: hash.to_string(),
+                date: None,
+                date_offset: None,
+                date_source: None,
+                thumb: Vec::new(),
+                media: MediaType::Image,
+            };
+            db::upsert(&conn, "foo-marker", hash, &info).unwrap();
+            db::set_camera(&conn, hash, &camera).unwrap();
+        }
+
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            db::cameras(&conn)
+                .run(())
+                .map(|v| v.unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                (some("Canon"), some("EOS 80D"), 2),
+                (some("SAMSUNG"), some("GT-I9100"), 1),
+            ]
+        );
+        assert_eq!(
+            db::lenses(&conn)
+                .run(())
+                .map(|v| v.unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                ("EF-S18-55mm".to_string(), 1),
+                ("EF50mm f/1.8 STM".to_string(), 1),
+            ]
+        );
+        assert_eq!(db::focal_lengths(&conn).run(()).count(), 3);
+
+        let visible = |filter| visible_with(&conn, db::set_camera_filter, filter);
+        assert_eq!(
+            visible(Some(db::CameraFilter {
+                make: some("samsung"),
+                ..Default::default()
+            })),
+            vec!["c"]
+        );
+        assert_eq!(
+            visible(Some(db::CameraFilter {
+                make: some("Canon"),
+                min_focal_length: Some(35.0),
+                max_focal_length: Some(85.0),
+                ..Default::default()
+            })),
+            vec!["a"]
+        );
+        assert_eq!(visible(None).len(), 4);
+    }
+
+    #[test]
+    fn upsert_filesystem_date_is_low_confidence() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let fs_date = NaiveDate::from_ymd(2023, 5, 1).and_hms(10, 0, 0);
+        let exif_date = NaiveDate::from_ymd(2022, 1, 22).and_hms(16, 53, 14);
+        let info = |date: Option<NaiveDateTime>, date_source| FileInfo {
+            hash: "fake-hash".to_string(),
+            date,
+            date_offset: None,
+            date_source,
+            thumb: Vec::new(),
+            media: MediaType::Image,
+        };
+        let date_in_db = || {
+            let files = all_files(&conn);
+            (files[0].date, files[0].date_source)
+        };
+
+        let fs_info = info(Some(fs_date), Some(DateSource::Filesystem));
+        db::upsert(&conn, "foo-marker", "a.jpg", &fs_info).unwrap();
+        assert_eq!(date_in_db(), (Some(fs_date), Some(DateSource::Filesystem)));
+
+        db::upsert(&conn, "foo-marker", "b.jpg", &info(None, None)).unwrap();
+        assert_eq!(date_in_db(), (Some(fs_date), Some(DateSource::Filesystem)));
+
+        let exif_info = info(Some(exif_date), Some(DateSource::Exif));
+        db::upsert(&conn, "foo-marker", "c.jpg", &exif_info).unwrap();
+        assert_eq!(date_in_db(), (Some(exif_date), Some(DateSource::Exif)));
+
+        db::upsert(&conn, "foo-marker", "a.jpg", &fs_info).unwrap();
+        assert_eq!(date_in_db(), (Some(exif_date), Some(DateSource::Exif)));
+    }
+
     #[test]
     fn upsert_changed_metadata_at_hash() {
         // arrange
@@ -387,7 +2160,10 @@ mod test {
             &FileInfo {
                 hash: hash.clone(),
                 date: None,
+                date_offset: None,
+                date_source: None,
                 thumb: vec![b'A'],
+                media: MediaType::Image,
             },
         )
         .unwrap();
@@ -397,7 +2173,10 @@ mod test {
             vec![FileInfo {
                 hash: hash.clone(),
                 date: None,
-                thumb: vec![b'A']
+                date_offset: None,
+                date_source: None,
+                thumb: vec![b'A'],
+                media: MediaType::Image,
             }]
         );
 
@@ -410,7 +2189,10 @@ mod test {
             &FileInfo {
                 hash: hash.clone(),
                 date: Some(date_2),
+                date_offset: None,
+                date_source: Some(DateSource::Exif),
                 thumb: vec![b'B'],
+                media: MediaType::Image,
             },
         )
         .unwrap();
@@ -423,7 +2205,10 @@ mod test {
             vec![FileInfo {
                 hash,
                 date: Some(date_2),
-                thumb: vec![b'B']
+                date_offset: None,
+                date_source: Some(DateSource::Exif),
+                thumb: vec![b'B'],
+                media: MediaType::Image,
             }]
         );
     }
@@ -0,0 +1,94 @@
+//! Finding groups of near-duplicate files, like the same photo resized, recompressed or slightly
+//! edited, by comparing their perceptual hashes (see: [crate::imaging::dhash]).
+
+use std::collections::HashMap;
+
+use crate::db::Rowid;
+
+/// Maximum [distance] of perceptual hashes of files which are considered near-duplicates.
+pub const MAX_DISTANCE: u32 = 6;
+
+/// Number of bits in which the perceptual hashes differ.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Groups files, whose perceptual hashes are at most `max_distance` apart, directly or through
+/// other files in the group. Only groups of at least two files are returned; files keep the order
+/// in which they were given, as do groups by their first file.
+pub fn clusters(hashes: &[(Rowid, u64)], max_distance: u32) -> Vec<Vec<Rowid>> {
+    // If hashes differ in at most `max_distance` bits, they must be equal in at least one of
+    // `max_distance + 1` blocks of bits. So only hashes sharing a block need to be compared.
+    let blocks = (max_distance + 1).min(64);
+    let mut buckets = HashMap::<(u32, u64), Vec<usize>>::new();
+    for (i, &(_, hash)) in hashes.iter().enumerate() {
+        for block in 0..blocks {
+            let (start, end) = (block * 64 / blocks, (block + 1) * 64 / blocks);
+            let mask = u64::MAX >> (64 - (end - start)) << start;
+            buckets.entry((block, hash & mask)).or_default().push(i);
+        }
+    }
+
+    // Union-find, with each set represented by its earliest file.
+    let mut parent = (0..hashes.len()).collect::<Vec<_>>();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for bucket in buckets.values() {
+        for (n, &i) in bucket.iter().enumerate() {
+            for &j in &bucket[n + 1..] {
+                if distance(hashes[i].1, hashes[j].1) > max_distance {
+                    continue;
+                }
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups = Vec::<Vec<Rowid>>::new();
+    let mut group_of_root = HashMap::new();
+    for (i, &(rowid, _)) in hashes.iter().enumerate() {
+        let r = root(&mut parent, i);
+        let g = *group_of_root.entry(r).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[g].push(rowid);
+    }
+    groups.retain(|g| g.len() > 1);
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clusters_by_distance() {
+        let hashes = [
+            (1, 0x0000_0000_0000_0000),
+            (2, 0xFFFF_0000_FFFF_0000),
+            (3, 0x0000_0000_0000_0007), // 3 bits from 1
+            (4, 0xFFFF_0000_FFFF_0001), // 1 bit from 2
+            (5, 0x0000_0000_0000_003F), // 3 bits from 3, 6 from 1
+            (6, 0x0F0F_0F0F_0F0F_0F0F),
+        ];
+        assert_eq!(clusters(&hashes, 3), vec![vec![1, 3, 5], vec![2, 4]]);
+        assert_eq!(clusters(&hashes, 0), Vec::<Vec<Rowid>>::new());
+        assert_eq!(clusters(&[(1, 42), (2, 42)], 0), vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn clusters_across_blocks() {
+        // Differences spread over all blocks must still be found.
+        let a = 0u64;
+        let b = 1 | 1 << 20 | 1 << 40 | 1 << 63;
+        assert_eq!(clusters(&[(1, a), (2, b)], 4), vec![vec![1, 2]]);
+        assert_eq!(clusters(&[(1, a), (2, b)], 3), Vec::<Vec<Rowid>>::new());
+    }
+}
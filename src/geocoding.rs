@@ -0,0 +1,213 @@
+//! Offline reverse geocoding: finding the populated place nearest to GPS coordinates, in a local
+//! copy of a GeoNames cities dataset (e.g. `cities1000.txt` from
+//! https://download.geonames.org/export/dump/).
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::Geocoding;
+use crate::interlude::*;
+use crate::model::{GpsPosition, Place};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+const KM_PER_DEGREE: f64 = 111.195;
+
+pub struct Places {
+    entries: Vec<Entry>,
+    /// Indexes of `entries` in cells of 1x1 degree, keyed by floor of latitude and longitude.
+    grid: HashMap<(i32, i32), Vec<usize>>,
+    max_km: f64,
+}
+
+struct Entry {
+    lat: f64,
+    lon: f64,
+    place: Place,
+}
+
+impl Places {
+    pub fn load(config: &Geocoding) -> Result<Places> {
+        let open = |path: &Path| {
+            File::open(path)
+                .map(BufReader::new)
+                .with_context(|| format!("Failed to open '{}'", path.display()))
+        };
+        let admin1 = config.admin1.as_deref().map(open).transpose()?;
+        Self::parse(open(&config.cities)?, admin1, config.max_km)
+            .with_context(|| format!("Failed to load '{}'", config.cities.display()))
+    }
+
+    /// Parses the tab-separated GeoNames `cities` file, and optionally the `admin1` codes file
+    /// (`admin1CodesASCII.txt`) to name the regions. Places farther than `max_km` from any
+    /// coordinates are not considered to describe them.
+    pub fn parse(
+        cities: impl BufRead,
+        admin1: Option<impl BufRead>,
+        max_km: f64,
+    ) -> Result<Places> {
+        let mut regions = HashMap::new();
+        if let Some(admin1) = admin1 {
+            for line in admin1.lines() {
+                let line = line?;
+                let mut columns = line.split('\t');
+                if let (Some(code), Some(name)) = (columns.next(), columns.next()) {
+                    regions.insert(code.to_owned(), name.to_owned());
+                }
+            }
+        }
+
+        let mut places = Places {
+            entries: Vec::new(),
+            grid: HashMap::new(),
+            max_km,
+        };
+        for (i, line) in cities.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let columns = line.split('\t').collect::<Vec<_>>();
+            let [_, name, _, _, lat, lon, class, _, country, _, admin1, ..] = columns[..] else {
+                bail!(ifmt!("too few columns in line " i + 1));
+            };
+            // Only populated places.
+            if class != "P" {
+                continue;
+            }
+            let (Ok(lat), Ok(lon)) = (lat.parse::<f64>(), lon.parse::<f64>()) else {
+                bail!(ifmt!("invalid coordinates in line " i + 1));
+            };
+            places
+                .grid
+                .entry(cell(lat, lon))
+                .or_default()
+                .push(places.entries.len());
+            places.entries.push(Entry {
+                lat,
+                lon,
+                place: Place {
+                    country: country.to_owned(),
+                    region: regions.get(&ifmt!(country "." admin1)).cloned(),
+                    city: name.to_owned(),
+                },
+            });
+        }
+        Ok(places)
+    }
+
+    pub fn nearest(&self, pos: &GpsPosition) -> Option<&Place> {
+        let (cell_lat, cell_lon) = cell(pos.lat, pos.lon);
+        let d_lat = (self.max_km / KM_PER_DEGREE).ceil() as i32;
+        // Degrees of longitude are shortest at the latitude farthest from the equator.
+        let max_lat = (pos.lat.abs() + self.max_km / KM_PER_DEGREE).min(90.0);
+        let lon_scale = max_lat.to_radians().cos();
+        let d_lon = if lon_scale > 1e-6 {
+            ((self.max_km / KM_PER_DEGREE / lon_scale).ceil() as i32).min(180)
+        } else {
+            180
+        };
+
+        let lons = if d_lon >= 180 {
+            (-180..180).collect::<Vec<_>>()
+        } else {
+            // Wrap around the 180th meridian.
+            (cell_lon - d_lon..=cell_lon + d_lon)
+                .map(|lon| (lon + 180).rem_euclid(360) - 180)
+                .collect()
+        };
+
+        let mut best: Option<(f64, &Entry)> = None;
+        for lat in cell_lat - d_lat..=cell_lat + d_lat {
+            for &lon in &lons {
+                for &i in self.grid.get(&(lat, lon)).into_iter().flatten() {
+                    let entry = &self.entries[i];
+                    let km = distance_km(pos.lat, pos.lon, entry.lat, entry.lon);
+                    if km <= self.max_km && best.map_or(true, |(best_km, _)| km < best_km) {
+                        best = Some((km, entry));
+                    }
+                }
+            }
+        }
+        best.map(|(_, entry)| &entry.place)
+    }
+}
+
+fn cell(lat: f64, lon: f64) -> (i32, i32) {
+    (lat.floor() as i32, lon.floor() as i32)
+}
+
+/// Great-circle distance, by the haversine formula.
+fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CITIES: &str = "\
+3094802\tKraków\tKrakow\tCracovia,Cracow\t50.06143\t19.93658\tP\tPPLA\tPL\t\t77\t1261\t\t\t804237\t\t219\tEurope/Warsaw\t2023-01-01
+3081368\tWieliczka\tWieliczka\t\t49.98738\t20.06432\tP\tPPL\tPL\t\t77\t1219\t\t\t23265\t\t270\tEurope/Warsaw\t2023-01-01
+756135\tWarsaw\tWarsaw\tWarszawa\t52.22977\t21.01178\tP\tPPLC\tPL\t\t78\t1465\t\t\t1702139\t\t113\tEurope/Warsaw\t2023-01-01
+2198148\tLevuka\tLevuka\t\t-17.68333\t178.83333\tP\tPPL\tFJ\t\t01\t\t\t\t1117\t\t\tPacific/Fiji\t2023-01-01
+2198190\tWaiyevo\tWaiyevo\t\t-16.7866\t179.9839\tP\tPPL\tFJ\t\t03\t\t\t\t3372\t\t\tPacific/Fiji\t2023-01-01
+3099434\tGdańsk Bay\tGdansk Bay\t\t54.5\t19.0\tH\tBAY\tPL\t\t82\t\t\t\t0\t\t\t\t2023-01-01
+";
+    const ADMIN1: &str =
+        "PL.77\tLesser Poland\tLesser Poland\t858785\nPL.78\tMasovia\tMasovia\t858787\n";
+
+    fn places(max_km: f64) -> Places {
+        Places::parse(CITIES.as_bytes(), Some(ADMIN1.as_bytes()), max_km).unwrap()
+    }
+
+    fn at(lat: f64, lon: f64) -> GpsPosition {
+        GpsPosition {
+            lat,
+            lon,
+            alt: None,
+        }
+    }
+
+    #[test]
+    fn nearest_place() {
+        let places = places(50.0);
+        assert_eq!(
+            places.nearest(&at(50.05, 19.94)),
+            Some(&Place {
+                country: "PL".to_string(),
+                region: Some("Lesser Poland".to_string()),
+                city: "Kraków".to_string(),
+            })
+        );
+        assert_eq!(places.nearest(&at(49.99, 20.05)).unwrap().city, "Wieliczka");
+        assert_eq!(places.nearest(&at(52.0, 21.0)).unwrap().city, "Warsaw");
+        // Too far from any place, and water bodies are not populated places.
+        assert_eq!(places.nearest(&at(54.5, 19.0)), None);
+        // Across the 180th meridian.
+        assert_eq!(places.nearest(&at(-16.8, -179.95)).unwrap().city, "Waiyevo");
+        assert_eq!(places.nearest(&at(-17.7, 178.8)).unwrap().region, None);
+    }
+
+    #[test]
+    fn nearest_place_max_distance() {
+        assert_eq!(places(5.0).nearest(&at(50.2, 19.94)), None);
+        assert_eq!(
+            places(50.0).nearest(&at(50.2, 19.94)).unwrap().city,
+            "Kraków"
+        );
+    }
+
+    #[test]
+    fn distance() {
+        let km = distance_km(50.06143, 19.93658, 52.22977, 21.01178);
+        assert!((km - 252.0).abs() < 2.0, "{km}");
+    }
+}
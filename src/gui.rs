@@ -1,11 +1,15 @@
 use iced::{Application, Element};
-use iced::widget::{scrollable, row};
+use iced::widget::{button, column, scrollable, row, text};
 use tracing::{span, Level};
 
 use crate::db::{SqlValue, SyncedDb};
+use crate::imaging::THUMBNAIL_SIZE;
 use crate::interlude::*;
+use crate::thumbnails;
 use crate::widgets::{
+    duplicates,
     gallery::{self, Gallery},
+    problems,
     tags::{self, tag},
 };
 
@@ -13,12 +17,27 @@ pub struct Gui {
     db: SyncedDb,
     gallery_selection: gallery::Selection,
     tags: tags::Panel,
+    mode: Mode,
+    tile_size: u32,
+    thumbnail_maker: thumbnails::Maker,
+}
+
+/// What is shown next to the tags panel.
+enum Mode {
+    Gallery,
+    Duplicates(duplicates::Panel),
+    Problems(problems::Panel),
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     OfTags(tags::Event),
     GallerySelection(gallery::Selection),
+    ShowGallery,
+    ShowDuplicates,
+    ShowProblems,
+    OfDuplicates(duplicates::Event),
+    SetTileSize(u32),
 }
 
 impl Application for Gui {
@@ -43,6 +62,9 @@ impl Application for Gui {
                     hidden: false,
                 },
             ]),
+            mode: Mode::Gallery,
+            tile_size: THUMBNAIL_SIZE,
+            thumbnail_maker: thumbnails::Maker::spawn(Arc::clone(&db)),
         };
         (gui, iced::Command::none())
     }
@@ -71,11 +93,26 @@ impl Application for Gui {
                 }
                 self.tags.update(m);
                 self.load_tags_for_selection();
+                // Files hidden meanwhile should disappear from their groups.
+                if let Mode::Duplicates(_) = self.mode {
+                    self.load_duplicates();
+                }
             }
             Message::GallerySelection(selection) => {
                 self.gallery_selection = selection;
                 self.load_tags_for_selection();
             }
+            Message::ShowGallery => self.mode = Mode::Gallery,
+            Message::ShowDuplicates => self.load_duplicates(),
+            Message::ShowProblems => {
+                let db = self.db.lock().unwrap();
+                self.mode = Mode::Problems(problems::Panel::load(&db));
+            }
+            Message::OfDuplicates(duplicates::Event::Select(rowid)) => {
+                self.gallery_selection = gallery::Selection::single(rowid);
+                self.load_tags_for_selection();
+            }
+            Message::SetTileSize(size) => self.tile_size = size,
         }
         iced::Command::none()
     }
@@ -88,19 +125,61 @@ impl Application for Gui {
         // FIXME: Milestone: add preview window on click
         // FIXME: Milestone: show some info about where img is present
 
-        let gallery = Gallery::new(Arc::clone(&self.db))
-            .with_selection(self.gallery_selection.clone())
-            .on_select(Message::GallerySelection);
+        let files: Element<_> = match &self.mode {
+            Mode::Gallery => {
+                let gallery = Gallery::new(Arc::clone(&self.db))
+                    .with_selection(self.gallery_selection.clone())
+                    .on_select(Message::GallerySelection)
+                    .tile_size(self.tile_size as f32)
+                    .thumbnail_maker(self.thumbnail_maker.clone());
+                // scrollable(gallery), // .height(iced::Length::Fill)
+                scrollable(gallery).width(iced::Length::Fill).into()
+            }
+            Mode::Duplicates(duplicates) => duplicates
+                .view(&self.gallery_selection)
+                .map(Message::OfDuplicates),
+            Mode::Problems(problems) => problems.view(),
+        };
+        // The button of the current mode is disabled.
+        let mode_button = |label, message, active| {
+            let b = button(text(label));
+            if active { b } else { b.on_press(message) }
+        };
+        let modes = row![
+            mode_button("All files", Message::ShowGallery, matches!(self.mode, Mode::Gallery)),
+            mode_button(
+                "Near-duplicates",
+                Message::ShowDuplicates,
+                matches!(self.mode, Mode::Duplicates(_)),
+            ),
+            mode_button(
+                "Scan problems",
+                Message::ShowProblems,
+                matches!(self.mode, Mode::Problems(_)),
+            ),
+        ].spacing(10);
+        // Tiles are as big as the thumbnails, so these are all the zoom levels.
+        let zoom = ["Small", "Medium", "Large"].into_iter().zip(thumbnails::SIZES);
+        let modes = zoom.fold(modes.push(text("Zoom:")), |modes, (label, size)| {
+            modes.push(mode_button(label, Message::SetTileSize(size), self.tile_size == size))
+        });
         let tags = self.tags.view().map(Message::OfTags);
-        row![
-            // scrollable(gallery), // .height(iced::Length::Fill)
-            scrollable(gallery).width(iced::Length::Fill),
-            tags,
+        column![
+            modes,
+            row![files, tags],
         ].into()
     }
 }
 
 impl Gui {
+    fn load_duplicates(&mut self) {
+        let prof_span = span!(Level::TRACE, "gui::load_duplicates");
+        let _enter = prof_span.enter();
+
+        let db = self.db.lock().unwrap();
+        self.mode = Mode::Duplicates(duplicates::Panel::load(&db));
+    }
+
     fn load_tags_for_selection(&mut self) {
         let prof_span = span!(Level::TRACE, "gui::load_tags_for_selection");
         let _enter = prof_span.enter();
@@ -0,0 +1,246 @@
+//! Digests of file contents by a few algorithms (see: [HashAlgorithm]). The SHA-1 digest is the
+//! identity of a file in the catalog, so it's always computed; digests by stronger algorithms are
+//! stored alongside it, and used to verify files when available.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use path_slash::PathBufExt;
+use rusqlite::Connection;
+use sha1::digest::DynDigest;
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::db::{self, SyncedDb};
+use crate::interlude::*;
+use crate::model::HashAlgorithm;
+
+/// Digests of the same contents, in the order of the algorithms they were asked for.
+pub type Digests = Vec<(HashAlgorithm, String)>;
+
+/// Computes digests by several algorithms in a single pass over the contents.
+pub struct Hasher {
+    hashers: Vec<(HashAlgorithm, Box<dyn DynDigest>)>,
+}
+
+impl Hasher {
+    pub fn new(algorithms: &[HashAlgorithm]) -> Self {
+        let hashers = algorithms
+            .iter()
+            .map(|&algorithm| {
+                let hasher: Box<dyn DynDigest> = match algorithm {
+                    HashAlgorithm::Sha1 => Box::new(Sha1::default()),
+                    HashAlgorithm::Sha256 => Box::new(Sha256::default()),
+                };
+                (algorithm, hasher)
+            })
+            .collect();
+        Self { hashers }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        for (_, hasher) in &mut self.hashers {
+            hasher.update(buf);
+        }
+    }
+
+    /// Hex-encoded digests.
+    pub fn finish(self) -> Digests {
+        self.hashers
+            .into_iter()
+            .map(|(algorithm, hasher)| {
+                let digest = hasher.finalize();
+                let hex = digest.iter().map(|b| format!("{b:02x}")).collect();
+                (algorithm, hex)
+            })
+            .collect()
+    }
+}
+
+/// Digests of `buf` by the `algorithms`.
+pub fn digests(buf: &[u8], algorithms: &[HashAlgorithm]) -> Digests {
+    let mut hasher = Hasher::new(algorithms);
+    hasher.update(buf);
+    hasher.finish()
+}
+
+/// Digests of contents streamed from `reader` in chunks, by the `algorithms`.
+pub fn digests_of_reader(
+    mut reader: impl Read,
+    algorithms: &[HashAlgorithm],
+) -> io::Result<Digests> {
+    let mut hasher = Hasher::new(algorithms);
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&chunk[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// Digests of contents of the file at `path` by the `algorithms`, without reading it whole into
+/// memory.
+pub fn digests_of_file(path: &Path, algorithms: &[HashAlgorithm]) -> io::Result<Digests> {
+    digests_of_reader(fs::File::open(path)?, algorithms)
+}
+
+/// The strongest known digest of the file with the SHA-1 `hash`, to verify it with.
+pub fn strongest_digest(db: &Connection, hash: &str) -> Result<(HashAlgorithm, String)> {
+    let strongest = db::digests(db, hash)?
+        .into_iter()
+        .max_by_key(|(algorithm, _)| *algorithm);
+    Ok(strongest.unwrap_or_else(|| (HashAlgorithm::Sha1, hash.to_string())))
+}
+
+/// Counts of files processed by [backfill].
+#[derive(Debug, Default, PartialEq)]
+pub struct Backfilled {
+    /// Files which got the digest.
+    pub added: usize,
+    /// Files of which no location could be read, e.g. because no tree with them is mounted.
+    pub unavailable: usize,
+    /// Files of which all readable locations have contents not matching their SHA-1 anymore.
+    pub mismatched: usize,
+}
+
+/// Adds digests by the `algorithm` to cataloged files which don't have them yet, e.g. ones
+/// scanned before it was configured (see: [crate::config::Config::digests]). Each file is read
+/// from the first of its locations which still matches its SHA-1, so that the new digest is of
+/// the same contents.
+pub fn backfill(db: &SyncedDb, algorithm: HashAlgorithm) -> Result<Backfilled> {
+    if algorithm == HashAlgorithm::Sha1 {
+        bail!("SHA-1 digests are the hashes of files, no need to backfill them");
+    }
+    let locations = db::missing_digests(&db.lock().unwrap(), algorithm)?;
+    let mut backfilled = Backfilled::default();
+    let mut locations = locations.into_iter().peekable();
+    while let Some((hash, root, relative)) = locations.next() {
+        let mut file_locations = vec![(root, relative)];
+        while let Some((_, root, relative)) = locations.next_if(|(next, _, _)| *next == hash) {
+            file_locations.push((root, relative));
+        }
+        let mut read_any = false;
+        let mut added = false;
+        for (root, relative) in file_locations {
+            let path = Path::new(&root).join(PathBuf::from_slash(&relative));
+            let Ok(digests) = digests_of_file(&path, &[HashAlgorithm::Sha1, algorithm]) else {
+                continue;
+            };
+            read_any = true;
+            let (sha1, digest) = (&digests[0].1, &digests[1].1);
+            if *sha1 != hash {
+                iprintln!("\nBAD HASH: " sha1 " != " hash " @ " path;?);
+                continue;
+            }
+            db::set_digest(&db.lock().unwrap(), &hash, algorithm, digest)?;
+            added = true;
+            break;
+        }
+        match (added, read_any) {
+            (true, _) => backfilled.added += 1,
+            (false, true) => backfilled.mismatched += 1,
+            (false, false) => backfilled.unavailable += 1,
+        }
+    }
+    Ok(backfilled)
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::marker;
+    use crate::model::FileInfo;
+
+    #[test]
+    fn known_digests() {
+        let digests = digests(b"abc", &[HashAlgorithm::Sha1, HashAlgorithm::Sha256]);
+        assert_eq!(
+            digests,
+            vec![
+                (
+                    HashAlgorithm::Sha1,
+                    "a9993e364706816aba3e25717850c26c9cd0d89d".to_string()
+                ),
+                (
+                    HashAlgorithm::Sha256,
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string()
+                ),
+            ]
+        );
+        let streamed =
+            digests_of_reader(&b"abc"[..], &[HashAlgorithm::Sha1, HashAlgorithm::Sha256]);
+        assert_eq!(streamed.unwrap(), digests);
+    }
+
+    #[test]
+    fn streamed_digests_across_chunks() {
+        let contents = (0..200_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let algorithms = [HashAlgorithm::Sha1, HashAlgorithm::Sha256];
+        assert_eq!(
+            digests_of_reader(&contents[..], &algorithms).unwrap(),
+            digests(&contents, &algorithms)
+        );
+    }
+
+    #[test]
+    fn backfill_from_matching_location() {
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("changed.jpg"), b"changed").unwrap();
+        fs::write(root.path().join("same.jpg"), b"same").unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let same = crate::scanning::hash(b"same");
+        db::upsert(
+            &conn,
+            "foo-marker",
+            "changed.jpg",
+            &FileInfo::with_hash(&same),
+        )
+        .unwrap();
+        db::upsert(&conn, "foo-marker", "same.jpg", &FileInfo::with_hash(&same)).unwrap();
+        db::upsert(
+            &conn,
+            "foo-marker",
+            "gone.jpg",
+            &FileInfo::with_hash("gone-hash"),
+        )
+        .unwrap();
+        db::set_marker_seen(
+            &conn,
+            "foo-marker",
+            root.path().to_str().unwrap(),
+            marker::FILE_NAME,
+            None,
+        )
+        .unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        let backfilled = backfill(&db, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(
+            backfilled,
+            Backfilled {
+                added: 1,
+                unavailable: 1,
+                mismatched: 0,
+            }
+        );
+        let sha256 = digests(b"same", &[HashAlgorithm::Sha256]).remove(0).1;
+        assert_eq!(
+            db::digests(&db.lock().unwrap(), &same).unwrap(),
+            vec![
+                (HashAlgorithm::Sha1, same.clone()),
+                (HashAlgorithm::Sha256, sha256)
+            ]
+        );
+        // Files already having the digest are not read again.
+        assert_eq!(backfill(&db, HashAlgorithm::Sha256).unwrap().added, 0);
+    }
+}
@@ -1,14 +1,137 @@
+use std::io;
+
 use chrono::naive::{NaiveDate, NaiveDateTime};
-use exif::{DateTime as ExifDateTime, Exif, Field, In, Tag, Value};
+use chrono::{Duration, FixedOffset};
+use exif::{Context, DateTime as ExifDateTime, Exif, Field, In, Tag, Value};
+use image::imageops::FilterType;
+use image::io::Reader as ImageReader;
+use image::jpeg::JpegDecoder;
+use image::{
+    DynamicImage, GenericImageView, GrayImage, ImageBuffer, ImageDecoder, ImageOutputFormat,
+    ImageResult, Rgb, RgbImage,
+};
+use jpeg_decoder::PixelFormat;
+use serde::{Deserialize, Serialize};
+
+use crate::model::{Camera, GpsPosition, ThumbnailSource};
+
+/// Exif tags holding the date and time of a photo.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DateTag {
+    /// When the photo was taken.
+    DateTimeOriginal,
+    /// When the photo was stored as digital data, e.g. when scanned.
+    DateTimeDigitized,
+    /// When the file was last changed, e.g. by photo editing software.
+    DateTime,
+}
+
+impl DateTag {
+    /// The order in which the tags are tried if not configured otherwise.
+    pub const DEFAULT_PRIORITY: &'static [DateTag] = &[
+        DateTag::DateTimeOriginal,
+        DateTag::DateTimeDigitized,
+        DateTag::DateTime,
+    ];
+
+    /// The tag itself, and its companion subsecond and UTC offset tags.
+    fn tags(self) -> (Tag, Tag, Tag) {
+        match self {
+            DateTag::DateTimeOriginal => (
+                Tag::DateTimeOriginal,
+                Tag::SubSecTimeOriginal,
+                Tag::OffsetTimeOriginal,
+            ),
+            DateTag::DateTimeDigitized => (
+                Tag::DateTimeDigitized,
+                Tag::SubSecTimeDigitized,
+                Tag::OffsetTimeDigitized,
+            ),
+            DateTag::DateTime => (Tag::DateTime, Tag::SubSecTime, Tag::OffsetTime),
+        }
+    }
+}
+
+/// How a photo's pixels are stored relative to how it should be shown, as described by the Exif
+/// `Orientation` tag. Names tell what has to be done to the stored image to show it upright.
+///
+/// See: <https://magnushoff.com/articles/jpeg-orientation/>
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    /// Stored upright.
+    Normal = 1,
+    FlipHorizontal = 2,
+    Rotate180 = 3,
+    FlipVertical = 4,
+    /// Mirror along the top-left to bottom-right diagonal.
+    Transpose = 5,
+    /// Rotate 90 degrees clockwise.
+    Rotate90 = 6,
+    /// Mirror along the top-right to bottom-left diagonal.
+    Transverse = 7,
+    /// Rotate 270 degrees clockwise.
+    Rotate270 = 8,
+}
+
+impl Orientation {
+    pub fn from_exif(value: u32) -> Option<Orientation> {
+        use Orientation::*;
+        [
+            Normal,
+            FlipHorizontal,
+            Rotate180,
+            FlipVertical,
+            Transpose,
+            Rotate90,
+            Transverse,
+            Rotate270,
+        ]
+        .into_iter()
+        .find(|o| *o as u32 == value)
+    }
+
+    /// Transforms the stored `img` so that it's shown upright.
+    pub fn deorient(self, img: DynamicImage) -> DynamicImage {
+        match self {
+            Orientation::Normal => img,
+            Orientation::FlipHorizontal => img.fliph(),
+            Orientation::Rotate180 => img.rotate180(),
+            Orientation::FlipVertical => img.flipv(),
+            Orientation::Transpose => img.rotate90().fliph(),
+            Orientation::Rotate90 => img.rotate90(),
+            Orientation::Transverse => img.rotate270().fliph(),
+            Orientation::Rotate270 => img.rotate270(),
+        }
+    }
+}
 
 pub trait ExifExt {
     fn datetime(&self, tag: Tag) -> Option<ExifDateTime>;
 
-    // TODO[LATER]: use some orientation enum / stricter type instead of raw u16
-    // for meaning, see: https://magnushoff.com/articles/jpeg-orientation/
-    // TODO[LATER]: test exif deorienting with cases from: https://github.com/recurser/exif-orientation-examples
-    // (see also: https://www.daveperrett.com/articles/2012/07/28/exif-orientation-handling-is-a-ghetto)
-    fn orientation(&self) -> Option<u16>;
+    /// Like [ExifExt::datetime], but with subseconds and UTC offset filled from the companion
+    /// `SubSecTime*` and `OffsetTime*` tags, where present.
+    fn date_tag(&self, tag: DateTag) -> Option<ExifDateTime>;
+
+    /// Date and time in UTC from the GPS receiver.
+    fn gps_datetime(&self) -> Option<NaiveDateTime>;
+
+    /// Where the photo was taken, in degrees north and east, and meters above sea level.
+    fn gps_position(&self) -> Option<GpsPosition>;
+
+    /// Camera, lens and exposure settings, and size of the image in pixels.
+    fn camera(&self) -> Camera;
+
+    /// How the image is stored relative to how it should be shown. Missing if the tag is absent
+    /// or has an invalid value, in which case the image is usually shown as stored.
+    fn orientation(&self) -> Option<Orientation>;
+
+    /// Largest JPEG stream embedded in the TIFF structure, if any. In camera RAW files (which are
+    /// TIFF-based) this is usually a preview of (nearly) full size, so we don't need to demosaic
+    /// the raw sensor data. In JPEG files, it's the small thumbnail from the Exif block.
+    fn embedded_jpeg(&self) -> Option<&[u8]>;
+
+    /// The small JPEG thumbnail from the Exif block (in the TIFF structure's second IFD).
+    fn exif_thumbnail(&self) -> Option<&[u8]>;
 }
 
 /// Macro making retrieval of Exif fields less visually cluttered.
@@ -37,23 +160,765 @@ impl ExifExt for Exif {
         }
     }
 
-    fn orientation(&self) -> Option<u16> {
-        exif_field! {
-            self[Tag::Orientation] as Value::Short{ref v} => {
-                Some(*v)
+    fn date_tag(&self, tag: DateTag) -> Option<ExifDateTime> {
+        let (tag, subsec_tag, offset_tag) = tag.tags();
+        let mut datetime = self.datetime(tag)?;
+        let ascii = |tag| {
+            exif_field! {
+                self[tag] as Value::Ascii{ref v} => {
+                    Some(v)
+                }
+            }
+        };
+        // Malformed companion tags are ignored, the main one is still useful.
+        if let Some(subsec) = ascii(subsec_tag) {
+            let _ = datetime.parse_subsec(subsec);
+        }
+        if let Some(offset) = ascii(offset_tag) {
+            let _ = datetime.parse_offset(offset);
+        }
+        Some(datetime)
+    }
+
+    fn gps_datetime(&self) -> Option<NaiveDateTime> {
+        let date = exif_field! {
+            self[Tag::GPSDateStamp] as Value::Ascii{ref v} => {
+                let v = std::str::from_utf8(v).ok()?;
+                NaiveDate::parse_from_str(v, "%Y:%m:%d").ok()
+            }
+        }?;
+        let seconds = match self.get_field(Tag::GPSTimeStamp, In::PRIMARY) {
+            Some(Field {
+                value: Value::Rational(ref hms),
+                ..
+            }) if hms.len() == 3 => {
+                hms[0].to_f64() * 3600.0 + hms[1].to_f64() * 60.0 + hms[2].to_f64()
+            }
+            _ => return None,
+        };
+        if !(0.0..86400.0).contains(&seconds) {
+            return None;
+        }
+        Some(date.and_hms_opt(0, 0, 0)? + Duration::milliseconds((seconds * 1000.0) as i64))
+    }
+
+    fn gps_position(&self) -> Option<GpsPosition> {
+        let rationals = |tag| match self.get_field(tag, In::PRIMARY) {
+            Some(Field {
+                value: Value::Rational(ref v),
+                ..
+            }) => Some(v.iter().map(|r| r.to_f64()).collect::<Vec<_>>()),
+            _ => None,
+        };
+        let degrees = |tag, ref_tag, negative_ref: &[u8]| {
+            let dms = rationals(tag)?;
+            let [d, m, s] = dms[..] else {
+                return None;
+            };
+            let sign = exif_field! {
+                self[ref_tag] as Value::Ascii{ref v} => {
+                    Some(if v.as_slice() == negative_ref { -1.0 } else { 1.0 })
+                }
             }
+            .unwrap_or(1.0);
+            Some(sign * (d + m / 60.0 + s / 3600.0)).filter(|v| v.is_finite())
+        };
+        let lat = degrees(Tag::GPSLatitude, Tag::GPSLatitudeRef, b"S")?;
+        let lon = degrees(Tag::GPSLongitude, Tag::GPSLongitudeRef, b"W")?;
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return None;
+        }
+        let below_sea_level = exif_field! {
+            self[Tag::GPSAltitudeRef] as Value::Byte{ref v} => {
+                Some(*v == 1)
+            }
+        }
+        .unwrap_or(false);
+        let alt = rationals(Tag::GPSAltitude)
+            .and_then(|v| v.first().copied())
+            .filter(|v| v.is_finite())
+            .map(|v| if below_sea_level { -v } else { v });
+        Some(GpsPosition { lat, lon, alt })
+    }
+
+    fn camera(&self) -> Camera {
+        let text = |tag| {
+            exif_field! {
+                self[tag] as Value::Ascii{ref v} => {
+                    // Cameras often pad the values with spaces or NULs.
+                    let v = String::from_utf8_lossy(v);
+                    let v = v.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+                    Some(v.to_owned()).filter(|v| !v.is_empty())
+                }
+            }
+        };
+        let positive = |tag| {
+            exif_field! {
+                self[tag] as Value::Rational{ref v} => {
+                    Some(v.to_f64()).filter(|v| v.is_finite() && *v > 0.0)
+                }
+            }
+        };
+        let uint = |tag| {
+            self.get_field(tag, In::PRIMARY)
+                .and_then(|f| f.value.get_uint(0))
+                .filter(|v| *v > 0)
+        };
+        Camera {
+            make: text(Tag::Make),
+            model: text(Tag::Model),
+            lens: text(Tag::LensModel),
+            focal_length: positive(Tag::FocalLength),
+            iso: uint(Tag::PhotographicSensitivity),
+            exposure: positive(Tag::ExposureTime),
+            width: uint(Tag::PixelXDimension),
+            height: uint(Tag::PixelYDimension),
+        }
+    }
+
+    fn orientation(&self) -> Option<Orientation> {
+        // Some software writes the value as LONG instead of SHORT.
+        self.get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0))
+            .and_then(Orientation::from_exif)
+    }
+
+    fn embedded_jpeg(&self) -> Option<&[u8]> {
+        let buf = self.buf();
+        let mut candidates = Vec::new();
+        for ifd in [In::PRIMARY, In::THUMBNAIL] {
+            for (offset_tag, length_tag) in JPEG_LOCATORS {
+                let offset = self
+                    .get_field(offset_tag, ifd)
+                    .and_then(|f| f.value.get_uint(0));
+                let length = self
+                    .get_field(length_tag, ifd)
+                    .and_then(|f| f.value.get_uint(0));
+                if let (Some(offset), Some(length)) = (offset, length) {
+                    candidates.push((offset, length));
+                }
+            }
+        }
+        // Many RAW formats (e.g. NEF, ARW, DNG) keep the big preview in a sub-IFD, which
+        // kamadak-exif doesn't parse, so we need to take a look there on our own.
+        if let Some(field) = self.get_field(TAG_SUB_IFDS, In::PRIMARY) {
+            for offset in field.value.iter_uint().into_iter().flatten() {
+                candidates.extend(ifd_jpeg_locators(
+                    buf,
+                    offset as usize,
+                    self.little_endian(),
+                ));
+            }
+        }
+        candidates
+            .into_iter()
+            .filter_map(|(offset, length)| {
+                let start = offset as usize;
+                buf.get(start..start.checked_add(length as usize)?)
+            })
+            .filter(|jpeg| jpeg.starts_with(&JPEG_SOI))
+            .max_by_key(|jpeg| jpeg.len())
+    }
+
+    fn exif_thumbnail(&self) -> Option<&[u8]> {
+        let uint = |tag| self.get_field(tag, In::THUMBNAIL)?.value.get_uint(0);
+        let start = uint(Tag::JPEGInterchangeFormat)? as usize;
+        let length = uint(Tag::JPEGInterchangeFormatLength)? as usize;
+        self.buf()
+            .get(start..start.checked_add(length)?)
+            .filter(|jpeg| jpeg.starts_with(&JPEG_SOI))
+    }
+}
+
+/// Thumbnails fit in a square of this many pixels.
+pub const THUMBNAIL_SIZE: u32 = 200;
+
+pub struct Thumbnail {
+    pub image: DynamicImage,
+    pub source: ThumbnailSource,
+    /// Size of the image the thumbnail was made of, if known.
+    pub full_size: Option<(u32, u32)>,
+}
+
+/// Makes a thumbnail of the image in `buf`, fitting in a square of `size` pixels. JPEGs are
+/// decoded at the smallest scale (1/8, 1/4, 1/2 or full) which still covers the thumbnail, which
+/// is much faster than decoding the full image; or not at all, if the thumbnail embedded in `exif`
+/// is big enough. Other images, and JPEGs which fail, are fully decoded with fallbacks (see:
+/// [decode_with_fallbacks]).
+pub fn thumbnail(buf: &[u8], exif: Option<&Exif>, size: u32) -> ImageResult<Thumbnail> {
+    if let Some(thumb) = scaled_jpeg_thumbnail(buf, exif, size) {
+        return Ok(thumb);
+    }
+    let (img, source) = decode_with_fallbacks(buf, exif)?;
+    Ok(Thumbnail {
+        full_size: (source != ThumbnailSource::ExifThumbnail).then(|| img.dimensions()),
+        image: img.resize(size, size, FilterType::CatmullRom),
+        source,
+    })
+}
+
+fn scaled_jpeg_thumbnail(buf: &[u8], exif: Option<&Exif>, size: u32) -> Option<Thumbnail> {
+    let mut decoder = JpegDecoder::new(io::Cursor::new(buf)).ok()?;
+    let full_size = decoder.dimensions();
+    let (w, h) = full_size;
+    // Size of the thumbnail, keeping the aspect ratio.
+    let scale = (size as f64 / w as f64)
+        .min(size as f64 / h as f64)
+        .min(1.0);
+    let (thumb_w, thumb_h) = (w as f64 * scale, h as f64 * scale);
+
+    let exif_thumbnail = exif
+        .and_then(|e| e.exif_thumbnail())
+        .and_then(|jpeg| image::load_from_memory(jpeg).ok())
+        .filter(|img| {
+            let (tw, th) = img.dimensions();
+            // Some cameras pad the thumbnail to 4:3 with black bars, which we don't want.
+            let same_aspect = (tw as f64 * h as f64 / (th as f64 * w as f64) - 1.0).abs() < 0.02;
+            same_aspect && tw as f64 >= thumb_w && th as f64 >= thumb_h
+        });
+    let (img, source) = match exif_thumbnail {
+        Some(img) => (img, ThumbnailSource::ExifThumbnail),
+        None => {
+            let requested = size.try_into().unwrap_or(u16::MAX);
+            decoder.scale(requested, requested).ok()?;
+            let img = DynamicImage::from_decoder(decoder).ok()?;
+            (img, ThumbnailSource::Decoder)
+        }
+    };
+    Some(Thumbnail {
+        image: img.resize(size, size, FilterType::CatmullRom),
+        source,
+        full_size: Some(full_size),
+    })
+}
+
+/// Decodes the image in `buf`. If the `image` crate fails, e.g. on JPEGs from some older cameras
+/// which it finds malformed, the more lenient `jpeg-decoder` crate is tried, and then the
+/// thumbnail embedded in `exif`. Returns the error of the first decoder if all of them fail.
+pub fn decode_with_fallbacks(
+    buf: &[u8],
+    exif: Option<&Exif>,
+) -> ImageResult<(DynamicImage, ThumbnailSource)> {
+    let err = match ImageReader::new(io::Cursor::new(buf))
+        .with_guessed_format()?
+        .decode()
+    {
+        Ok(img) => return Ok((img, ThumbnailSource::Decoder)),
+        Err(err) => err,
+    };
+    if let Some(img) = decode_jpeg_leniently(buf) {
+        return Ok((img, ThumbnailSource::FallbackDecoder));
+    }
+    let exif_thumbnail = exif
+        .and_then(|e| e.exif_thumbnail())
+        .and_then(|jpeg| image::load_from_memory(jpeg).ok());
+    match exif_thumbnail {
+        Some(img) => Ok((img, ThumbnailSource::ExifThumbnail)),
+        None => Err(err),
+    }
+}
+
+fn decode_jpeg_leniently(buf: &[u8]) -> Option<DynamicImage> {
+    let mut decoder = jpeg_decoder::Decoder::new(buf);
+    let pixels = decoder.decode().ok()?;
+    let info = decoder.info()?;
+    let (w, h) = (info.width.into(), info.height.into());
+    match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(w, h, pixels).map(DynamicImage::ImageLuma8),
+        PixelFormat::L16 => {
+            let pixels = pixels
+                .chunks_exact(2)
+                .map(|v| u16::from_ne_bytes([v[0], v[1]]))
+                .collect();
+            ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageLuma16)
+        }
+        PixelFormat::RGB24 => RgbImage::from_raw(w, h, pixels).map(DynamicImage::ImageRgb8),
+        PixelFormat::CMYK32 => {
+            // Converted the same way as newer versions of the `image` crate do.
+            let pixels = pixels
+                .chunks_exact(4)
+                .flat_map(|v| {
+                    let k = 255 - u16::from(v[3]);
+                    [0, 1, 2].map(|i| ((255 - u16::from(v[i])) * k / 255) as u8)
+                })
+                .collect();
+            RgbImage::from_raw(w, h, pixels).map(DynamicImage::ImageRgb8)
         }
     }
 }
 
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+
+/// Pairs of TIFF tags (offset, length) which may point to an embedded JPEG stream.
+const JPEG_LOCATORS: [(Tag, Tag); 2] = [
+    (Tag::JPEGInterchangeFormat, Tag::JPEGInterchangeFormatLength),
+    (Tag::StripOffsets, Tag::StripByteCounts),
+];
+
+/// TIFF-EP `SubIFDs` tag, not known to kamadak-exif.
+const TAG_SUB_IFDS: Tag = Tag(Context::Tiff, 0x14A);
+
+/// Minimal parser of a single TIFF IFD at `offset` in `buf`, returning (offset, length) pairs of
+/// any JPEG streams it points to.
+fn ifd_jpeg_locators(buf: &[u8], offset: usize, little_endian: bool) -> Vec<(u32, u32)> {
+    let u16_at = |at: usize| {
+        let b = buf.get(at..at + 2)?;
+        let b = [b[0], b[1]];
+        Some(if little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    };
+    let u32_at = |at: usize| {
+        let b = buf.get(at..at + 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    };
+    const TIFF_SHORT: u16 = 3;
+
+    let n_entries = u16_at(offset).unwrap_or(0) as usize;
+    let mut values = Vec::new();
+    for i in 0..n_entries {
+        let entry = offset + 2 + 12 * i;
+        let (tag, typ) = match (u16_at(entry), u16_at(entry + 2)) {
+            (Some(tag), Some(typ)) => (tag, typ),
+            _ => break,
+        };
+        let value = if typ == TIFF_SHORT {
+            u16_at(entry + 8).map(u32::from)
+        } else {
+            u32_at(entry + 8)
+        };
+        if let Some(value) = value {
+            values.push((tag, value));
+        }
+    }
+    let find = |tag: Tag| {
+        values
+            .iter()
+            .find(|(t, _)| *t == tag.number())
+            .map(|(_, v)| *v)
+    };
+    JPEG_LOCATORS
+        .into_iter()
+        .filter_map(|(offset_tag, length_tag)| Some((find(offset_tag)?, find(length_tag)?)))
+        .collect()
+}
+
+/// JPEG thumbnail for files we can't render a preview of (yet), like videos: a dark tile with a
+/// "play" triangle in the middle.
+pub fn video_placeholder() -> ImageResult<Vec<u8>> {
+    const W: u32 = 200;
+    const H: u32 = 150;
+    let img = RgbImage::from_fn(W, H, |x, y| {
+        // Triangle pointing right, with its left edge at x0 and tip at x1.
+        let (x0, x1, half_h) = (W / 2 - 20, W / 2 + 30, 30);
+        let dy = (y as i64 - (H / 2) as i64).unsigned_abs() as u32;
+        if (x0..=x1).contains(&x) && dy * (x1 - x0) <= (x1 - x) * half_h {
+            Rgb([220, 220, 220])
+        } else {
+            Rgb([64, 64, 64])
+        }
+    });
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(img).write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))?;
+    Ok(jpeg)
+}
+
+/// Perceptual "difference hash" of the image: each bit tells if brightness grows between
+/// neighboring cells of a 9x8 grid. Unlike a cryptographic hash, it changes only a little when the
+/// image is resized, recompressed or slightly edited, so similar images can be found by the
+/// [Hamming distance](crate::duplicates::distance) of their hashes.
+///
+/// See: <https://www.hackerfactor.com/blog/index.php?/archives/529-Kind-of-Like-That.html>
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let grid = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = grid.get_pixel(x + 1, y)[0] > grid.get_pixel(x, y)[0];
+            hash = hash << 1 | brighter as u64;
+        }
+    }
+    hash
+}
+
 pub trait ExifDateTimeExt {
     fn to_naive_opt(&self) -> Option<NaiveDateTime>;
+    fn offset_opt(&self) -> Option<FixedOffset>;
 }
 
 impl ExifDateTimeExt for ExifDateTime {
     fn to_naive_opt(&self) -> Option<NaiveDateTime> {
         NaiveDate::from_ymd_opt(self.year.into(), self.month.into(), self.day.into()).and_then(
-            |date| date.and_hms_opt(self.hour.into(), self.minute.into(), self.second.into()),
+            |date| {
+                date.and_hms_nano_opt(
+                    self.hour.into(),
+                    self.minute.into(),
+                    self.second.into(),
+                    self.nanosecond.unwrap_or(0),
+                )
+            },
         )
     }
+
+    fn offset_opt(&self) -> Option<FixedOffset> {
+        self.offset
+            .and_then(|minutes| FixedOffset::east_opt(i32::from(minutes) * 60))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use exif::Reader as ExifReader;
+    use image::GenericImageView;
+
+    use super::*;
+
+    const FAKE_JPEG: &[u8] = &[0xFF, 0xD8, 0xFF, 0xD9];
+
+    /// Builds a little-endian TIFF with a single IFD holding the given (tag, LONG value) entries.
+    fn tiff_with_ifd0(entries: &[(u16, u32)], tail: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0x49, 0x49, 0x2A, 0x00, 8, 0, 0, 0];
+        buf.extend((entries.len() as u16).to_le_bytes());
+        for (tag, value) in entries {
+            buf.extend(tag.to_le_bytes());
+            buf.extend(4u16.to_le_bytes()); // LONG
+            buf.extend(1u32.to_le_bytes());
+            buf.extend(value.to_le_bytes());
+        }
+        buf.extend(0u32.to_le_bytes()); // no next IFD
+        buf.extend(tail);
+        buf
+    }
+
+    enum TestValue {
+        Ascii(&'static str),
+        Byte(u8),
+        Short(u16),
+        Rational(&'static [(u32, u32)]),
+    }
+
+    /// Builds a little-endian TIFF with a sub-IFD (e.g. Exif or GPS one, depending on the
+    /// `pointer_tag`) holding the given entries.
+    fn tiff_with_sub_ifd(pointer_tag: u16, entries: &[(u16, TestValue)]) -> Vec<u8> {
+        let sub_ifd_offset: u32 = 8 + 2 + 12 + 4;
+        let mut buf = tiff_with_ifd0(&[(pointer_tag, sub_ifd_offset)], &[]);
+        let mut data_offset = sub_ifd_offset + 2 + 12 * entries.len() as u32 + 4;
+        let mut data = Vec::new();
+        buf.extend((entries.len() as u16).to_le_bytes());
+        for (tag, value) in entries {
+            let (typ, count, mut value) = match value {
+                TestValue::Ascii(s) => {
+                    let mut v = s.as_bytes().to_vec();
+                    v.push(0);
+                    (2u16, v.len(), v)
+                }
+                TestValue::Byte(b) => (1, 1, vec![*b]),
+                TestValue::Short(s) => (3, 1, s.to_le_bytes().to_vec()),
+                TestValue::Rational(rs) => {
+                    let v = rs
+                        .iter()
+                        .flat_map(|(n, d)| n.to_le_bytes().into_iter().chain(d.to_le_bytes()))
+                        .collect();
+                    (5, rs.len(), v)
+                }
+            };
+            buf.extend(tag.to_le_bytes());
+            buf.extend(typ.to_le_bytes());
+            buf.extend((count as u32).to_le_bytes());
+            if value.len() <= 4 {
+                value.resize(4, 0);
+                buf.extend(value);
+            } else {
+                buf.extend(data_offset.to_le_bytes());
+                data_offset += value.len() as u32;
+                data.extend(value);
+            }
+        }
+        buf.extend(0u32.to_le_bytes()); // no next IFD
+        buf.extend(data);
+        buf
+    }
+
+    #[test]
+    fn date_tag_with_subsec_and_offset() {
+        let buf = tiff_with_sub_ifd(
+            0x8769,
+            &[
+                (0x9003, TestValue::Ascii("2021:03:04 05:06:07")), // DateTimeOriginal
+                (0x9011, TestValue::Ascii("+02:00")),              // OffsetTimeOriginal
+                (0x9291, TestValue::Ascii("25")),                  // SubSecTimeOriginal
+            ],
+        );
+        let exif = ExifReader::new().read_raw(buf).unwrap();
+
+        let datetime = exif.date_tag(DateTag::DateTimeOriginal).unwrap();
+        assert_eq!(datetime.offset, Some(120));
+        assert_eq!(
+            datetime.to_naive_opt(),
+            Some(NaiveDate::from_ymd(2021, 3, 4).and_hms_milli(5, 6, 7, 250))
+        );
+        assert!(exif.date_tag(DateTag::DateTimeDigitized).is_none());
+    }
+
+    #[test]
+    fn gps_position_and_time() {
+        let buf = tiff_with_sub_ifd(
+            0x8825,
+            &[
+                (0x1, TestValue::Ascii("N")),
+                (0x2, TestValue::Rational(&[(50, 1), (3, 1), (4115, 100)])),
+                (0x3, TestValue::Ascii("W")),
+                (0x4, TestValue::Rational(&[(19, 1), (56, 1), (0, 1)])),
+                (0x5, TestValue::Byte(1)),
+                (0x6, TestValue::Rational(&[(25, 2)])),
+                (0x7, TestValue::Rational(&[(15, 1), (5, 1), (58, 1)])),
+                (0x1D, TestValue::Ascii("2021:03:04")),
+            ],
+        );
+        let exif = ExifReader::new().read_raw(buf).unwrap();
+
+        let gps = exif.gps_position().unwrap();
+        assert!((gps.lat - 50.061431).abs() < 1e-6);
+        assert!((gps.lon + 19.933333).abs() < 1e-6);
+        assert_eq!(gps.alt, Some(-12.5));
+        assert_eq!(
+            exif.gps_datetime(),
+            Some(NaiveDate::from_ymd(2021, 3, 4).and_hms(15, 5, 58))
+        );
+    }
+
+    #[test]
+    fn camera_settings() {
+        let buf = tiff_with_sub_ifd(
+            0x8769,
+            &[
+                (0x829A, TestValue::Rational(&[(1, 250)])), // ExposureTime
+                (0x8827, TestValue::Short(400)),            // PhotographicSensitivity
+                (0x920A, TestValue::Rational(&[(50, 1)])),  // FocalLength
+                (0xA002, TestValue::Short(6000)),           // PixelXDimension
+                (0xA003, TestValue::Short(0)),              // PixelYDimension
+                (0xA434, TestValue::Ascii("EF50mm f/1.8 STM  ")), // LensModel
+            ],
+        );
+        let exif = ExifReader::new().read_raw(buf).unwrap();
+
+        assert_eq!(
+            exif.camera(),
+            Camera {
+                lens: Some("EF50mm f/1.8 STM".to_owned()),
+                focal_length: Some(50.0),
+                iso: Some(400),
+                exposure: Some(0.004),
+                width: Some(6000),
+                ..Default::default()
+            }
+        );
+    }
+
+    /// The same picture, as stored by a camera held in each of the orientations, must look the
+    /// same once deoriented. Mirrors the sample set from:
+    /// <https://github.com/recurser/exif-orientation-examples>
+    #[test]
+    fn deorient_all_orientations() {
+        // An asymmetric 3x2 picture, where each pixel is distinct.
+        let upright = RgbImage::from_fn(3, 2, |x, y| Rgb([x as u8, y as u8, 0]));
+        let upright = DynamicImage::ImageRgb8(upright);
+        let stored = [
+            (1, upright.clone()),
+            (2, upright.fliph()),
+            (3, upright.rotate180()),
+            (4, upright.flipv()),
+            (5, upright.rotate90().fliph()),
+            (6, upright.rotate270()),
+            (7, upright.rotate90().flipv()),
+            (8, upright.rotate90()),
+        ];
+        for (value, stored) in stored {
+            let exif = ExifReader::new()
+                .read_raw(tiff_with_ifd0(&[(0x112, value)], &[]))
+                .unwrap();
+            let orientation = exif.orientation().unwrap();
+            assert_eq!(orientation as u32, value);
+            let shown = orientation.deorient(stored);
+            assert_eq!(shown.dimensions(), (3, 2), "orientation {value}");
+            assert_eq!(
+                shown.as_rgb8().unwrap().as_raw(),
+                upright.as_rgb8().unwrap().as_raw(),
+                "orientation {value}"
+            );
+        }
+
+        assert_eq!(Orientation::from_exif(0), None);
+        assert_eq!(Orientation::from_exif(9), None);
+    }
+
+    #[test]
+    fn dhash_of_similar_images() {
+        let img = RgbImage::from_fn(90, 60, |x, y| {
+            let v = 128.0 + 100.0 * (x as f64 / 9.0).sin() * (y as f64 / 11.0).cos();
+            Rgb([v as u8, v as u8, 255 - v as u8])
+        });
+        let img = DynamicImage::ImageRgb8(img);
+        let hash = dhash(&img);
+
+        let mut recompressed = Vec::new();
+        img.resize(45, 30, FilterType::CatmullRom)
+            .write_to(&mut recompressed, ImageOutputFormat::Jpeg(50))
+            .unwrap();
+        let recompressed = image::load_from_memory(&recompressed).unwrap();
+        assert!((hash ^ dhash(&recompressed)).count_ones() <= 4);
+
+        assert!((hash ^ dhash(&img.rotate90())).count_ones() > 16);
+    }
+
+    #[test]
+    fn embedded_jpeg_in_ifd0() {
+        let tail_offset: u32 = 8 + 2 + 2 * 12 + 4;
+        let buf = tiff_with_ifd0(&[(0x201, tail_offset), (0x202, 4)], FAKE_JPEG);
+        let exif = ExifReader::new().read_raw(buf).unwrap();
+        assert_eq!(exif.embedded_jpeg(), Some(FAKE_JPEG));
+    }
+
+    #[test]
+    fn embedded_jpeg_in_sub_ifd() {
+        let sub_ifd_offset: u32 = 8 + 2 + 12 + 4;
+        let tail_offset = sub_ifd_offset + 2 + 2 * 12 + 4;
+        let mut sub_ifd = Vec::new();
+        sub_ifd.extend(2u16.to_le_bytes());
+        for (tag, value) in [(0x201u16, tail_offset), (0x202, 4)] {
+            sub_ifd.extend(tag.to_le_bytes());
+            sub_ifd.extend(4u16.to_le_bytes());
+            sub_ifd.extend(1u32.to_le_bytes());
+            sub_ifd.extend(value.to_le_bytes());
+        }
+        sub_ifd.extend(0u32.to_le_bytes());
+        sub_ifd.extend(FAKE_JPEG);
+        let buf = tiff_with_ifd0(&[(0x14A, sub_ifd_offset)], &sub_ifd);
+        let exif = ExifReader::new().read_raw(buf).unwrap();
+        assert_eq!(exif.embedded_jpeg(), Some(FAKE_JPEG));
+    }
+
+    fn jpeg_of_size(width: u32, height: u32) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        jpeg
+    }
+
+    /// Builds a little-endian TIFF with an empty-ish IFD0, and IFD1 pointing to the `jpeg`.
+    fn tiff_with_thumbnail(jpeg: &[u8]) -> Vec<u8> {
+        let ifd1_offset: u32 = 8 + 2 + 12 + 4;
+        let jpeg_offset = ifd1_offset + 2 + 2 * 12 + 4;
+        let mut buf = tiff_with_ifd0(&[(0x112, 1)], &[]);
+        buf.truncate(buf.len() - 4);
+        buf.extend(ifd1_offset.to_le_bytes());
+        buf.extend(2u16.to_le_bytes());
+        for (tag, value) in [(0x201u16, jpeg_offset), (0x202, jpeg.len() as u32)] {
+            buf.extend(tag.to_le_bytes());
+            buf.extend(4u16.to_le_bytes());
+            buf.extend(1u32.to_le_bytes());
+            buf.extend(value.to_le_bytes());
+        }
+        buf.extend(0u32.to_le_bytes());
+        buf.extend(jpeg);
+        buf
+    }
+
+    #[test]
+    fn exif_thumbnail_in_ifd1() {
+        let exif = ExifReader::new()
+            .read_raw(tiff_with_thumbnail(FAKE_JPEG))
+            .unwrap();
+        assert_eq!(exif.exif_thumbnail(), Some(FAKE_JPEG));
+
+        let buf = tiff_with_ifd0(&[(0x201, 1000), (0x202, 4)], &[]);
+        let exif = ExifReader::new().read_raw(buf).unwrap();
+        assert_eq!(exif.exif_thumbnail(), None);
+    }
+
+    #[test]
+    fn decode_fallbacks() {
+        let jpeg = jpeg_of_size(16, 8);
+        let (img, source) = decode_with_fallbacks(&jpeg, None).unwrap();
+        assert_eq!(
+            (img.dimensions(), source),
+            ((16, 8), ThumbnailSource::Decoder)
+        );
+
+        // Some cameras write 0 as the end of spectral selection in baseline scans, which only
+        // the fallback decoder accepts.
+        let mut lenient = jpeg.clone();
+        let sos = lenient.windows(2).position(|w| w == [0xFF, 0xDA]).unwrap();
+        let components = lenient[sos + 4] as usize;
+        lenient[sos + 5 + 2 * components + 1] = 0;
+        let (img, source) = decode_with_fallbacks(&lenient, None).unwrap();
+        assert_eq!(
+            (img.dimensions(), source),
+            ((16, 8), ThumbnailSource::FallbackDecoder)
+        );
+
+        let exif = ExifReader::new()
+            .read_raw(tiff_with_thumbnail(&jpeg))
+            .unwrap();
+        let (img, source) = decode_with_fallbacks(b"garbage", Some(&exif)).unwrap();
+        assert_eq!(
+            (img.dimensions(), source),
+            ((16, 8), ThumbnailSource::ExifThumbnail)
+        );
+
+        assert!(decode_with_fallbacks(b"garbage", None).is_err());
+    }
+
+    #[test]
+    fn scaled_thumbnails() {
+        let thumb = thumbnail(&jpeg_of_size(1600, 1200), None, 200).unwrap();
+        assert_eq!(thumb.image.dimensions(), (200, 150));
+        assert_eq!(thumb.source, ThumbnailSource::Decoder);
+        assert_eq!(thumb.full_size, Some((1600, 1200)));
+
+        // The Exif thumbnail is used only if it's big enough and has the same aspect ratio.
+        let photo = jpeg_of_size(1600, 1200);
+        for (exif_size, expected) in [
+            ((320, 240), ThumbnailSource::ExifThumbnail),
+            ((160, 120), ThumbnailSource::Decoder),
+            ((320, 320), ThumbnailSource::Decoder),
+        ] {
+            let exif_jpeg = jpeg_of_size(exif_size.0, exif_size.1);
+            let exif = ExifReader::new()
+                .read_raw(tiff_with_thumbnail(&exif_jpeg))
+                .unwrap();
+            let thumb = thumbnail(&photo, Some(&exif), 200).unwrap();
+            assert_eq!(
+                (thumb.source, thumb.full_size),
+                (expected, Some((1600, 1200)))
+            );
+            assert_eq!(thumb.image.dimensions(), (200, 150));
+        }
+
+        // If the photo can't be decoded, the size of the small Exif thumbnail is not reported.
+        let exif = ExifReader::new()
+            .read_raw(tiff_with_thumbnail(&jpeg_of_size(16, 8)))
+            .unwrap();
+        let thumb = thumbnail(b"garbage", Some(&exif), 200).unwrap();
+        assert_eq!(thumb.image.dimensions(), (200, 100));
+        assert_eq!(
+            (thumb.source, thumb.full_size),
+            (ThumbnailSource::ExifThumbnail, None)
+        );
+    }
+
+    #[test]
+    fn embedded_jpeg_missing() {
+        let buf = tiff_with_ifd0(&[(0x201, 1000), (0x202, 4)], &[]);
+        let exif = ExifReader::new().read_raw(buf).unwrap();
+        assert_eq!(exif.embedded_jpeg(), None);
+    }
 }
@@ -0,0 +1,145 @@
+//! Minimal parser of the ISO base media file format (a.k.a. ISO-BMFF, the container of MP4, MOV,
+//! 3GP and similar video files), extracting only the few bits of metadata we're interested in.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+/// Creation time of the movie, as stored in the `moov/mvhd` box. Per the spec it should be in UTC,
+/// though some cameras are known to store local time there. Only the `moov` box is read from the
+/// file, skipping over the others, which hold the (big) media data.
+pub fn creation_time_from(mut file: impl Read + Seek) -> io::Result<Option<NaiveDateTime>> {
+    let mut header = [0u8; 16];
+    loop {
+        if let Err(err) = file.read_exact(&mut header[..8]) {
+            return match err.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(None),
+                _ => Err(err),
+            };
+        }
+        let (header_len, size) = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+            1 => {
+                file.read_exact(&mut header[8..16])?;
+                (16, u64::from_be_bytes(header[8..16].try_into().unwrap()))
+            }
+            // The box extends to the end of file, so it's the last one.
+            0 if &header[4..8] != b"moov" => return Ok(None),
+            0 => {
+                let mut moov = Vec::new();
+                file.read_to_end(&mut moov)?;
+                return Ok(movie_creation_time(&moov));
+            }
+            n => (8, n.into()),
+        };
+        let Some(contents_len) = size.checked_sub(header_len) else {
+            return Ok(None);
+        };
+        if &header[4..8] == b"moov" {
+            let mut moov = Vec::new();
+            file.by_ref().take(contents_len).read_to_end(&mut moov)?;
+            return Ok(movie_creation_time(&moov));
+        }
+        file.seek(SeekFrom::Current(
+            contents_len.try_into().unwrap_or(i64::MAX),
+        ))?;
+    }
+}
+
+fn movie_creation_time(moov: &[u8]) -> Option<NaiveDateTime> {
+    let mvhd = find_box(moov, b"mvhd")?;
+    // The box starts with 1 byte of version and 3 bytes of flags.
+    let seconds = match mvhd.first()? {
+        0 => u32::from_be_bytes(mvhd.get(4..8)?.try_into().ok()?).into(),
+        1 => u64::from_be_bytes(mvhd.get(4..12)?.try_into().ok()?),
+        _ => return None,
+    };
+    // Zero is commonly stored when the time is not known.
+    if seconds == 0 {
+        return None;
+    }
+    let epoch = NaiveDate::from_ymd_opt(1904, 1, 1)?.and_hms_opt(0, 0, 0)?;
+    epoch.checked_add_signed(Duration::seconds(seconds.try_into().ok()?))
+}
+
+/// Contents of the first box of type `typ` found among the sibling boxes in `buf`.
+fn find_box<'a>(mut buf: &'a [u8], typ: &[u8; 4]) -> Option<&'a [u8]> {
+    while buf.len() >= 8 {
+        let (header, size) = match u32::from_be_bytes(buf[0..4].try_into().unwrap()) {
+            // Extended 64-bit size follows the type.
+            1 => (
+                16,
+                u64::from_be_bytes(buf.get(8..16)?.try_into().unwrap()) as usize,
+            ),
+            // Box extends to the end of file.
+            0 => (8, buf.len()),
+            n => (8, n as usize),
+        };
+        if size < header || size > buf.len() {
+            return None;
+        }
+        if &buf[4..8] == typ {
+            return Some(&buf[header..size]);
+        }
+        buf = &buf[size..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mp4_box(typ: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut buf = ((8 + contents.len()) as u32).to_be_bytes().to_vec();
+        buf.extend(typ);
+        buf.extend(contents);
+        buf
+    }
+
+    fn mvhd_v0(creation_time: u32) -> Vec<u8> {
+        let mut contents = vec![0, 0, 0, 0];
+        contents.extend(creation_time.to_be_bytes());
+        contents.extend([0; 92]);
+        mp4_box(b"mvhd", &contents)
+    }
+
+    #[test]
+    fn creation_time_v0() {
+        let mut buf = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        buf.extend(mp4_box(b"mdat", &[0xAB; 100]));
+        let moov = [mp4_box(b"trak", &[]), mvhd_v0(3_725_654_400)].concat();
+        buf.extend(mp4_box(b"moov", &moov));
+        assert_eq!(
+            creation_time_from(io::Cursor::new(&buf)).unwrap(),
+            Some(NaiveDate::from_ymd(2022, 1, 22).and_hms(0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn creation_time_from_file() {
+        let mut buf = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        buf.extend(mp4_box(b"mdat", &[0xAB; 100]));
+        buf.extend(mp4_box(b"moov", &mvhd_v0(3_725_654_400)));
+        assert_eq!(
+            creation_time_from(io::Cursor::new(&buf)).unwrap(),
+            Some(NaiveDate::from_ymd(2022, 1, 22).and_hms(0, 0, 0))
+        );
+
+        // Past the end of file, seeking succeeds but nothing more can be read.
+        buf.truncate(30);
+        assert_eq!(creation_time_from(io::Cursor::new(&buf)).unwrap(), None);
+    }
+
+    #[test]
+    fn creation_time_unknown() {
+        let buf = mp4_box(b"moov", &mvhd_v0(0));
+        assert_eq!(creation_time_from(io::Cursor::new(&buf)).unwrap(), None);
+    }
+
+    #[test]
+    fn creation_time_truncated() {
+        let mut buf = mp4_box(b"moov", &mvhd_v0(3_725_654_400));
+        buf.truncate(20);
+        assert_eq!(creation_time_from(io::Cursor::new(&buf)).unwrap(), None);
+    }
+}
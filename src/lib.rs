@@ -1,10 +1,17 @@
 pub mod config;
 pub mod db;
+pub mod duplicates;
+pub mod geocoding;
 pub mod gui;
+pub mod hashing;
 pub mod imaging;
 pub mod interlude;
+pub mod isobmff;
+pub mod marker;
 pub mod model;
 pub mod pathwalk;
 pub mod res;
 pub mod scanning;
+pub mod scrub;
+pub mod thumbnails;
 pub mod widgets;
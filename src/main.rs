@@ -17,8 +17,6 @@ use backer::scanning::*;
 // - [LATER] show which rule index detected date in which file (also Exif)
 // - [LATER] also show files that were still "missed" by detection
 // TODO: use date-from-path regexps
-// TODO: skip small images (threshold size configurable in JSON - dimensions or bytes?)
-// - or maybe just skip them in viewer for now?
 // TODO: re-scan disks and re-generate data in DB
 // - for above step, we should probably have an iterator easily generating file paths from marker
 //   path (similar as already exists for initial scan, but extracted for reuse)
@@ -27,7 +25,6 @@ use backer::scanning::*;
 //   specific marker's tree
 //   - it should use same filters as the main files iterator (incl. extension, jpeg size)
 // TODO: merge 'view' and 'main' binaries
-// TODO: deorient thumbnails stored into DB
 
 fn main() {
     if let Err(err) = run() {
@@ -0,0 +1,515 @@
+//! Marker files, identifying the roots of backup trees.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::Connection as DbConnection;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+use crate::config::Discover;
+use crate::db;
+use crate::interlude::*;
+
+/// Default name of a marker file, placed in the root directory of a tree.
+pub const FILE_NAME: &str = "backer-id.json";
+
+/// Contents of a marker file. Only the `id` is mandatory, as older marker files contain nothing
+/// else.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Marker {
+    /// Identifies the tree in the catalog (as `backend_tag` of file locations).
+    pub id: String,
+    /// Human-readable name of the tree, e.g. "Grey WD disk from the drawer".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+    /// Random identifier, generated when the marker file was created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    #[serde(default)]
+    pub backend: Backend,
+    /// Whether the tree is only an archive, that should not be modified.
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    #[default]
+    Disk,
+    Ipfs,
+}
+
+impl Marker {
+    /// New marker with a freshly generated UUID and creation date.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            label: None,
+            created: Some(Utc::now()),
+            uuid: Some(Uuid::new_v4()),
+            backend: Backend::default(),
+            read_only: false,
+            notes: None,
+        }
+    }
+}
+
+/// Reads the marker file, returning it together with the root directory of its tree.
+// TODO[LATER]: accept Path and return Result<(Path,...)> with proper lifetime
+pub fn read(file_path: &Path) -> Result<(PathBuf, Marker)> {
+    let parent = file_path.parent().ok_or_else(|| {
+        anyhow!(
+            "Could not split parent directory of '{}'",
+            file_path.display()
+        )
+    })?;
+
+    let file = File::open(file_path)
+        .with_context(|| format!("Failed to open '{}'", file_path.display()))?;
+    let m: Marker = serde_json::from_reader(io::BufReader::new(file))?;
+
+    Ok((parent.to_owned(), m))
+}
+
+/// Creates a new marker file in `dir`, refusing to overwrite an existing one, or to reuse an id
+/// already known in the catalog. Returns path of the created file.
+pub fn init(dir: &Path, marker: &Marker, db: &DbConnection) -> Result<PathBuf> {
+    check_new_id(db, &marker.id)?;
+    let path = dir.join(FILE_NAME);
+    write(&path, marker, OpenOptions::new().create_new(true))?;
+    Ok(path)
+}
+
+fn check_new_id(db: &DbConnection, id: &str) -> Result<()> {
+    if id.is_empty() {
+        bail!("marker id must not be empty");
+    }
+    if db::marker_known(db, id)? || db::marker_seen(db, id)?.is_some() {
+        bail!("marker id {:?} is already used in the catalog", id);
+    }
+    Ok(())
+}
+
+fn write(path: &Path, marker: &Marker, options: &mut OpenOptions) -> Result<()> {
+    let file = options
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to create '{}'", path.display()))?;
+    let mut w = io::BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut w, marker)?;
+    writeln!(w)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Two physical trees claiming the same marker id, e.g. after a whole disk was copied.
+#[derive(Error, Debug)]
+pub enum Conflict {
+    #[error(
+        "marker {id:?} at {root:?} is also present at {other:?}, the tree looks cloned; \
+         give one of them a new id with `marker reid DIR NEW_ID --clone`"
+    )]
+    Cloned {
+        id: String,
+        root: PathBuf,
+        other: PathBuf,
+    },
+    #[error(
+        "marker {id:?} at {root:?} has UUID {uuid}, but {seen} was seen previously; \
+         if it's the same tree, accept it with `marker confirm DIR`, \
+         otherwise give it a new id with `marker reid DIR NEW_ID --clone`"
+    )]
+    Uuid {
+        id: String,
+        root: PathBuf,
+        uuid: String,
+        seen: String,
+    },
+}
+
+/// Canonical path of the root directory of the tree with the marker file at `file_path`, and the
+/// name of the file.
+fn locate(file_path: &Path) -> Result<(PathBuf, String)> {
+    let file_name = file_path
+        .file_name()
+        .ok_or_else(|| anyhow!("No file name in '{}'", file_path.display()))?;
+    let root = match file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve '{}'", root.display()))?;
+    Ok((root, file_name.to_string_lossy().into_owned()))
+}
+
+/// Verifies that the tree with the marker file at `file_path` is the same one which was seen with
+/// the marker's id during previous scans, then records it as the latest seen. A different root
+/// alone is fine, as disks may get mounted at various places; but if the previous root still
+/// contains a marker file with the same id, two trees share it and a [Conflict] is returned.
+pub fn check_identity(db: &DbConnection, file_path: &Path, marker: &Marker) -> Result<()> {
+    let (root, file_name) = locate(file_path)?;
+    let uuid = marker.uuid.map(|uuid| uuid.to_string());
+    if let Some((seen_root, seen_file_name, seen_uuid)) = db::marker_seen(db, &marker.id)? {
+        if let (Some(uuid), Some(seen)) = (&uuid, seen_uuid) {
+            if *uuid != seen {
+                return Err(Conflict::Uuid {
+                    id: marker.id.clone(),
+                    root,
+                    uuid: uuid.clone(),
+                    seen,
+                }
+                .into());
+            }
+        }
+        let other = PathBuf::from(seen_root);
+        if other != root {
+            let seen_file_name = seen_file_name.as_deref().unwrap_or(FILE_NAME);
+            if let Ok((_, found)) = read(&other.join(seen_file_name)) {
+                if found.id == marker.id {
+                    return Err(Conflict::Cloned {
+                        id: marker.id.clone(),
+                        root,
+                        other,
+                    }
+                    .into());
+                }
+            }
+        }
+    }
+    let root = root.to_string_lossy();
+    db::set_marker_seen(db, &marker.id, &root, &file_name, uuid.as_deref())
+}
+
+/// Accepts the tree with the marker file at `file_path` as the one identified by the marker's id,
+/// regardless of what was seen before.
+pub fn confirm(db: &DbConnection, file_path: &Path, marker: &Marker) -> Result<()> {
+    let (root, file_name) = locate(file_path)?;
+    let uuid = marker.uuid.map(|uuid| uuid.to_string());
+    let root = root.to_string_lossy();
+    db::set_marker_seen(db, &marker.id, &root, &file_name, uuid.as_deref())
+}
+
+/// Rewrites the marker file at `file_path` with a `new_id` and a fresh UUID, migrating catalog
+/// rows of the old id. If `clone` is set, the tree is a copy of another one which keeps the old
+/// id, so the rows are copied instead of moved. Returns the new marker.
+pub fn reid(db: &DbConnection, file_path: &Path, new_id: &str, clone: bool) -> Result<Marker> {
+    check_new_id(db, new_id)?;
+    let (_, old) = read(file_path)?;
+    let new = Marker {
+        id: new_id.to_owned(),
+        uuid: Some(Uuid::new_v4()),
+        ..old.clone()
+    };
+
+    // The new marker file is only put in place once the catalog is migrated, so that a failure
+    // of either leaves both untouched.
+    let tmp = file_path.with_extension("json.new");
+    write(&tmp, &new, OpenOptions::new().create(true).truncate(true))?;
+    let replaced = migrate_and_replace(db, file_path, &tmp, &old.id, &new, clone);
+    if replaced.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    replaced?;
+    Ok(new)
+}
+
+/// Migrates catalog rows of the `old_id` to the `new` marker and moves the marker file written
+/// at `tmp` over `file_path`. The original file is restored if the migration can't be committed.
+fn migrate_and_replace(
+    db: &DbConnection,
+    file_path: &Path,
+    tmp: &Path,
+    old_id: &str,
+    new: &Marker,
+    clone: bool,
+) -> Result<()> {
+    let original =
+        fs::read(file_path).with_context(|| format!("Failed to read '{}'", file_path.display()))?;
+    let tx = db.unchecked_transaction()?;
+    db::rename_marker(&tx, old_id, &new.id, clone)?;
+    confirm(&tx, file_path, new)?;
+    fs::rename(tmp, file_path)
+        .with_context(|| format!("Failed to replace '{}'", file_path.display()))?;
+    if let Err(err) = tx.commit() {
+        fs::write(file_path, original)
+            .with_context(|| format!("Failed to restore '{}'", file_path.display()))?;
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// Searches for marker files under the `discover.roots`. Directories which can't be accessed are
+/// silently skipped, as it's normal for some mount points to be not readable.
+pub fn discover(discover: &Discover) -> Vec<PathBuf> {
+    discover
+        .roots
+        .iter()
+        .flat_map(|root| WalkDir::new(root).max_depth(discover.depth))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && entry.file_name() == FILE_NAME)
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::model::FileInfo;
+
+    #[test]
+    fn read_legacy() {
+        let root = tempdir().unwrap();
+        let path = root.path().join(FILE_NAME);
+        std::fs::write(&path, r#"{"id": "foo-marker"}"#).unwrap();
+
+        let (dir, marker) = read(&path).unwrap();
+
+        assert_eq!(dir, root.path());
+        assert_eq!(
+            marker,
+            Marker {
+                id: "foo-marker".to_string(),
+                label: None,
+                created: None,
+                uuid: None,
+                backend: Backend::Disk,
+                read_only: false,
+                notes: None,
+            }
+        );
+    }
+
+    #[test]
+    fn init_and_read() {
+        let root = tempdir().unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let marker = Marker {
+            label: Some("Foo disk".to_string()),
+            read_only: true,
+            ..Marker::new("foo-marker")
+        };
+
+        let path = init(root.path(), &marker, &conn).unwrap();
+
+        assert_eq!(
+            read(&path).unwrap(),
+            (root.path().to_owned(), marker.clone())
+        );
+        // Never overwrite.
+        assert!(init(root.path(), &Marker::new("bar-marker"), &conn).is_err());
+        assert_eq!(read(&path).unwrap().1, marker);
+    }
+
+    #[test]
+    fn discover_by_depth() {
+        let root = tempdir().unwrap();
+        for dir in ["user/a", "user/b", "user/b/c", "user/d/e"] {
+            let dir = root.path().join(dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(FILE_NAME), "{}").unwrap();
+        }
+        std::fs::write(root.path().join("user/other.json"), "{}").unwrap();
+
+        let mut found = discover(&Discover {
+            roots: vec![root.path().to_owned(), root.path().join("missing")],
+            depth: 3,
+        });
+        found.sort();
+
+        assert_eq!(
+            found,
+            vec![
+                root.path().join("user/a").join(FILE_NAME),
+                root.path().join("user/b").join(FILE_NAME),
+            ]
+        );
+    }
+
+    fn locations(conn: &DbConnection) -> Vec<(String, String)> {
+        conn.prepare("SELECT backend_tag, path FROM location ORDER BY backend_tag, path")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn identity_moved_tree() {
+        let (a, b) = (tempdir().unwrap(), tempdir().unwrap());
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let marker = Marker::new("foo-marker");
+
+        check_identity(&conn, &a.path().join(FILE_NAME), &marker).unwrap();
+        // Nothing at the old root any more, so the tree was just mounted elsewhere.
+        check_identity(&conn, &b.path().join(FILE_NAME), &marker).unwrap();
+        check_identity(&conn, &b.path().join(FILE_NAME), &marker).unwrap();
+    }
+
+    #[test]
+    fn identity_cloned_tree() {
+        let (a, b) = (tempdir().unwrap(), tempdir().unwrap());
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let marker = Marker::new("foo-marker");
+        init(a.path(), &marker, &conn).unwrap();
+        std::fs::copy(a.path().join(FILE_NAME), b.path().join(FILE_NAME)).unwrap();
+
+        check_identity(&conn, &a.path().join(FILE_NAME), &marker).unwrap();
+        let err = check_identity(&conn, &b.path().join(FILE_NAME), &marker).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Conflict::Cloned { .. })));
+    }
+
+    #[test]
+    fn identity_cloned_tree_custom_name() {
+        let (a, b) = (tempdir().unwrap(), tempdir().unwrap());
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let marker = Marker::new("foo-marker");
+        let contents = serde_json::to_string(&marker).unwrap();
+        std::fs::write(a.path().join("marker.json"), &contents).unwrap();
+        std::fs::write(b.path().join("marker.json"), &contents).unwrap();
+
+        check_identity(&conn, &a.path().join("marker.json"), &marker).unwrap();
+        let err = check_identity(&conn, &b.path().join("marker.json"), &marker).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Conflict::Cloned { .. })));
+    }
+
+    #[test]
+    fn identity_different_uuid() {
+        let a = tempdir().unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let other = Marker::new("foo-marker");
+
+        check_identity(&conn, &a.path().join(FILE_NAME), &Marker::new("foo-marker")).unwrap();
+        let err = check_identity(&conn, &a.path().join(FILE_NAME), &other).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Conflict::Uuid { .. })));
+
+        confirm(&conn, &a.path().join(FILE_NAME), &other).unwrap();
+        check_identity(&conn, &a.path().join(FILE_NAME), &other).unwrap();
+    }
+
+    #[test]
+    fn reid_clone() {
+        let (a, b) = (tempdir().unwrap(), tempdir().unwrap());
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let marker = Marker::new("foo-marker");
+        init(a.path(), &marker, &conn).unwrap();
+        std::fs::copy(a.path().join(FILE_NAME), b.path().join(FILE_NAME)).unwrap();
+        db::upsert(
+            &conn,
+            "foo-marker",
+            "foo.jpg",
+            &FileInfo::with_hash("fake-hash"),
+        )
+        .unwrap();
+        check_identity(&conn, &a.path().join(FILE_NAME), &marker).unwrap();
+
+        let new = reid(&conn, &b.path().join(FILE_NAME), "bar-marker", true).unwrap();
+
+        assert_eq!(read(&b.path().join(FILE_NAME)).unwrap().1, new);
+        assert_ne!(new.uuid, marker.uuid);
+        assert_eq!(new.created, marker.created);
+        assert!(!b.path().join("backer-id.json.new").exists());
+        assert_eq!(
+            locations(&conn),
+            vec![
+                ("bar-marker".to_string(), "foo.jpg".to_string()),
+                ("foo-marker".to_string(), "foo.jpg".to_string()),
+            ]
+        );
+        check_identity(&conn, &a.path().join(FILE_NAME), &marker).unwrap();
+        check_identity(&conn, &b.path().join(FILE_NAME), &new).unwrap();
+        // Ids already in the catalog can't be reused.
+        assert!(reid(&conn, &a.path().join(FILE_NAME), "bar-marker", false).is_err());
+    }
+
+    #[test]
+    fn reid_rename() {
+        let a = tempdir().unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let marker = Marker::new("foo-marker");
+        init(a.path(), &marker, &conn).unwrap();
+        db::upsert(
+            &conn,
+            "foo-marker",
+            "foo.jpg",
+            &FileInfo::with_hash("fake-hash"),
+        )
+        .unwrap();
+        check_identity(&conn, &a.path().join(FILE_NAME), &marker).unwrap();
+
+        reid(&conn, &a.path().join(FILE_NAME), "bar-marker", false).unwrap();
+
+        assert_eq!(
+            locations(&conn),
+            vec![("bar-marker".to_string(), "foo.jpg".to_string())]
+        );
+        assert!(db::marker_seen(&conn, "foo-marker").unwrap().is_none());
+    }
+
+    #[test]
+    fn reid_failure_keeps_marker() {
+        let a = tempdir().unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let marker = Marker::new("foo-marker");
+        init(a.path(), &marker, &conn).unwrap();
+        db::upsert(
+            &conn,
+            "foo-marker",
+            "foo.jpg",
+            &FileInfo::with_hash("fake-hash"),
+        )
+        .unwrap();
+        let original = fs::read(a.path().join(FILE_NAME)).unwrap();
+        // Fails the migration after some of the rows were copied.
+        conn.execute("DROP TABLE scrub_mismatch", []).unwrap();
+
+        assert!(reid(&conn, &a.path().join(FILE_NAME), "bar-marker", true).is_err());
+
+        assert_eq!(fs::read(a.path().join(FILE_NAME)).unwrap(), original);
+        assert!(!a.path().join("backer-id.json.new").exists());
+        assert_eq!(
+            locations(&conn),
+            vec![("foo-marker".to_string(), "foo.jpg".to_string())]
+        );
+    }
+
+    #[test]
+    fn init_known_id() {
+        let root = tempdir().unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        db::upsert(
+            &conn,
+            "foo-marker",
+            "foo.jpg",
+            &FileInfo::with_hash("fake-hash"),
+        )
+        .unwrap();
+
+        assert!(init(root.path(), &Marker::new("foo-marker"), &conn).is_err());
+        assert!(!root.path().join(FILE_NAME).exists());
+    }
+}
@@ -1,8 +1,133 @@
 use chrono::naive::NaiveDateTime;
+use chrono::{Duration, FixedOffset};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, PartialEq)]
 pub struct FileInfo {
     pub hash: String,
+    /// Local time at the place where the file was created.
     pub date: Option<NaiveDateTime>,
+    /// Offset of the `date` from UTC, if known.
+    pub date_offset: Option<FixedOffset>,
+    pub date_source: Option<DateSource>,
     pub thumb: Vec<u8>,
+    pub media: MediaType,
+}
+
+impl FileInfo {
+    /// The `date` converted to UTC, for ordering files from different timezones. If the offset is
+    /// unknown, the local `date` is the best approximation we have.
+    pub fn instant(&self) -> Option<NaiveDateTime> {
+        let offset = self.date_offset.map_or(0, |o| o.local_minus_utc());
+        self.date.map(|d| d - Duration::seconds(offset.into()))
+    }
+
+    /// An undated image with the `hash` and no thumbnail, for tests to catalog.
+    #[cfg(test)]
+    pub fn with_hash(hash: &str) -> Self {
+        Self {
+            hash: hash.to_string(),
+            date: None,
+            date_offset: None,
+            date_source: None,
+            thumb: Vec::new(),
+            media: MediaType::Image,
+        }
+    }
+}
+
+/// Geographic coordinates, in degrees north and east (negative for south and west), and altitude
+/// in meters above sea level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsPosition {
+    pub lat: f64,
+    pub lon: f64,
+    pub alt: Option<f64>,
+}
+
+/// Name of the populated place nearest to where a file was created.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Place {
+    /// ISO-3166 two-letter code.
+    pub country: String,
+    pub region: Option<String>,
+    pub city: String,
+}
+
+/// Camera and exposure settings a photo was taken with, and its size.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Camera {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    /// Actual (not 35mm-equivalent) focal length, in millimeters.
+    pub focal_length: Option<f64>,
+    pub iso: Option<u32>,
+    /// Exposure time, in seconds.
+    pub exposure: Option<f64>,
+    /// Size in pixels.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// Where the date of a file was taken from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DateSource {
+    Exif,
+    /// Metadata of a video container.
+    Container,
+    /// Matched by a [crate::config::DatePath] rule.
+    Path,
+    /// Timestamp from the filesystem, which changes easily when copying files; so it's
+    /// low-confidence and never overrides a date from any other source.
+    Filesystem,
+}
+
+/// How the thumbnail of a file was made.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThumbnailSource {
+    /// Decoded by the `image` crate.
+    Decoder,
+    /// Decoded by the more lenient `jpeg-decoder` crate, after the `image` crate failed.
+    FallbackDecoder,
+    /// Taken from the small thumbnail embedded in Exif, either because it's big enough, or after
+    /// all decoders failed.
+    ExifThumbnail,
+    /// Generic picture for files we can't render a preview of, like videos.
+    Placeholder,
+    /// Not made yet, as thumbnails are deferred (see: [crate::scanning::make_pending_thumbnails]);
+    /// the thumbnail is empty meanwhile.
+    Pending,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MediaType {
+    #[default]
+    Image,
+    /// Note: for videos, we currently store just a placeholder thumbnail.
+    Video,
+}
+
+/// Algorithm of a digest of file contents. SHA-1 digests identify files in the catalog; digests
+/// by stronger algorithms can be stored alongside them, to verify files with (see:
+/// [crate::hashing]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgorithm {
+    // Ordered from the weakest, so that the strongest one is the maximum.
+    Sha1,
+    Sha256,
+}
+
+/// Why a file found during a scan could not be cataloged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProblemKind {
+    /// The file or directory could not be listed, e.g. for lack of permissions.
+    Access,
+    /// The file's contents could not be read.
+    Read,
+    /// The image could not be decoded.
+    Decode,
+    /// A RAW file has no JPEG preview embedded, and we can't demosaic it.
+    NoPreview,
 }
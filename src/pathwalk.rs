@@ -3,10 +3,16 @@
 
 pub mod matcher {
     use std::ffi::{OsStr, OsString};
+    use std::path::Path;
+
+    use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 
     pub trait DirEntry {
+        /// Path of the file/directory, relative to the root of the walked tree.
+        fn relative_path(&self) -> &Path;
         /// Extension of the file/directory's name, if present.
         fn extension(&self) -> Option<&OsStr>;
+        fn is_dir(&self) -> bool;
     }
 
     pub trait Matcher {
@@ -39,70 +45,221 @@ pub mod matcher {
         }
     }
 
+    /// Matches relative paths of entries against a set of glob patterns (any of them must match).
+    /// See [globset] for the syntax; notably, `*` does not match `/`, but `**` does.
+    #[derive(Clone, Debug)]
+    pub struct Globs(GlobSet);
+
+    impl Globs {
+        pub fn new<S: AsRef<str>>(
+            patterns: impl IntoIterator<Item = S>,
+        ) -> Result<Self, globset::Error> {
+            let mut set = GlobSetBuilder::new();
+            for pattern in patterns {
+                set.add(
+                    GlobBuilder::new(pattern.as_ref())
+                        .literal_separator(true)
+                        .build()?,
+                );
+            }
+            Ok(Self(set.build()?))
+        }
+
+        pub fn boxed(self) -> Box<dyn Matcher> {
+            Box::new(self)
+        }
+    }
+
+    impl Matcher for Globs {
+        fn matches(&self, entry: &dyn DirEntry) -> bool {
+            self.0.is_match(entry.relative_path())
+        }
+    }
+
+    /// Matches if all of the inner matchers match (or if there are none).
+    pub struct All(pub Vec<Box<dyn Matcher>>);
+
+    impl Matcher for All {
+        fn matches(&self, entry: &dyn DirEntry) -> bool {
+            self.0.iter().all(|m| m.matches(entry))
+        }
+    }
+
+    /// Matches if any of the inner matchers matches.
+    pub struct Any(pub Vec<Box<dyn Matcher>>);
+
+    impl Matcher for Any {
+        fn matches(&self, entry: &dyn DirEntry) -> bool {
+            self.0.iter().any(|m| m.matches(entry))
+        }
+    }
+
+    pub struct Not(pub Box<dyn Matcher>);
+
+    impl Matcher for Not {
+        fn matches(&self, entry: &dyn DirEntry) -> bool {
+            !self.0.matches(entry)
+        }
+    }
+
     #[cfg(test)]
     mod test {
+        use std::path::PathBuf;
+
         use super::*;
 
-        struct MockEntry(Option<OsString>);
+        struct MockEntry(PathBuf);
 
         impl DirEntry for MockEntry {
+            fn relative_path(&self) -> &Path {
+                &self.0
+            }
             fn extension(&self) -> Option<&OsStr> {
-                self.0.as_deref()
+                self.0.extension()
+            }
+            fn is_dir(&self) -> bool {
+                false
             }
         }
 
+        fn entry(path: &str) -> MockEntry {
+            MockEntry(path.into())
+        }
+
         #[test]
         fn case_insensitive_jpeg_extensions() {
             let jpegs = CaseInsensitiveExtensions(vec!["jpg".into(), "jpeg".into()]);
 
             // Positive
-            assert!(jpegs.matches(&MockEntry(Some("jpg".into()))));
-            assert!(jpegs.matches(&MockEntry(Some("jpeg".into()))));
-            assert!(jpegs.matches(&MockEntry(Some("JPG".into()))));
-            assert!(jpegs.matches(&MockEntry(Some("JPEG".into()))));
+            assert!(jpegs.matches(&entry("foo.jpg")));
+            assert!(jpegs.matches(&entry("foo.jpeg")));
+            assert!(jpegs.matches(&entry("foo.JPG")));
+            assert!(jpegs.matches(&entry("foo.JPEG")));
+
+            // Negative
+            assert!(!jpegs.matches(&entry("foo")));
+            assert!(!jpegs.matches(&entry("foo.png")));
+        }
+
+        #[test]
+        fn globs() {
+            let globs = Globs::new(["**/@eaDir", "cache/*.jpg"]).unwrap();
+
+            // Positive
+            assert!(globs.matches(&entry("@eaDir")));
+            assert!(globs.matches(&entry("foo/bar/@eaDir")));
+            assert!(globs.matches(&entry("cache/foo.jpg")));
 
             // Negative
-            assert!(!jpegs.matches(&MockEntry(None)));
-            assert!(!jpegs.matches(&MockEntry(Some("png".into()))));
+            assert!(!globs.matches(&entry("foo/@eaDir/bar.jpg")));
+            assert!(!globs.matches(&entry("cache/foo/bar.jpg")));
+            assert!(!globs.matches(&entry("foo/cache/bar.jpg")));
+        }
+
+        #[test]
+        fn combinators() {
+            let jpegs = || CaseInsensitiveExtensions::boxed(["jpg"]);
+            let cache = || Globs::new(["cache/**"]).unwrap().boxed();
+            let m = All(vec![jpegs(), Box::new(Not(cache()))]);
+            assert!(m.matches(&entry("foo/bar.jpg")));
+            assert!(!m.matches(&entry("cache/bar.jpg")));
+            assert!(!m.matches(&entry("foo/bar.png")));
+
+            let m = Any(vec![jpegs(), cache()]);
+            assert!(m.matches(&entry("foo/bar.jpg")));
+            assert!(m.matches(&entry("cache/bar.png")));
+            assert!(!m.matches(&entry("foo/bar.png")));
+
+            assert!(All(vec![]).matches(&entry("foo")));
+            assert!(!Any(vec![]).matches(&entry("foo")));
         }
     }
 }
 
 pub mod walker {
     use std::ffi::OsStr;
+    use std::fmt;
     use std::path::{Path, PathBuf};
 
     use anyhow::{anyhow, Result};
+    use ignore::gitignore::{Gitignore, GitignoreBuilder};
+    use serde::{Deserialize, Serialize};
     use walkdir::WalkDir;
 
     use super::matcher as m;
 
-    /// Note: for now implicitly assumed to be a file, not a dir.
-    /// (Behavior for symlinks is currently unspecified.)
+    /// Note: only files are emitted by [FilesIterator], directories are passed just to matchers.
+    /// For symbolic links, see [Symlinks].
     // TODO[LATER]: try making this thin wrapper around `&Path`
     #[derive(Debug)]
     pub struct DirEntry {
         relative_path: PathBuf,
+        is_dir: bool,
+        alias_of: Option<PathBuf>,
     }
 
     impl DirEntry {
         pub fn relative_path(&self) -> &Path {
             self.relative_path.as_ref()
         }
+
+        /// For symbolic links emitted with [Symlinks::Alias], relative path of the link's target.
+        pub fn alias_of(&self) -> Option<&Path> {
+            self.alias_of.as_deref()
+        }
+    }
+
+    /// Path of the entry which caused an error emitted by [FilesIterator], prefixed with the root
+    /// of the tree. Attached to the error as context, so it can be retrieved with
+    /// [anyhow::Error::downcast_ref].
+    #[derive(Debug)]
+    pub struct ErrorPath(pub PathBuf);
+
+    impl fmt::Display for ErrorPath {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Failed to access {:?}", self.0)
+        }
+    }
+
+    /// What to do when a symbolic link is encountered in the tree.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum Symlinks {
+        /// Emit an error for every link.
+        #[default]
+        Error,
+        /// Silently ignore links.
+        Skip,
+        /// Treat links as the files/directories they point to. Links pointing outside the root of
+        /// the tree are ignored, and link loops are reported as errors.
+        Follow,
+        /// Emit links to files inside the tree as separate entries, with [DirEntry::alias_of]
+        /// pointing to the target. Other links are ignored.
+        Alias,
     }
 
     impl m::DirEntry for DirEntry {
+        fn relative_path(&self) -> &Path {
+            self.relative_path.as_ref()
+        }
         fn extension(&self) -> Option<&OsStr> {
             self.relative_path.extension()
         }
+        fn is_dir(&self) -> bool {
+            self.is_dir
+        }
     }
 
     pub struct Files {
         root: PathBuf,
         matchers: Vec<Box<dyn m::Matcher>>,
+        excluded: Vec<Box<dyn m::Matcher>>,
+        ignore_file_name: Option<String>,
+        symlinks: Symlinks,
     }
 
     impl Files {
+        /// Files will be emitted if they match any of the `matchers`.
         // TODO[LATER]: can we avoid Box in arg somehow?
         pub fn new(
             root: impl AsRef<Path>,
@@ -111,17 +268,42 @@ pub mod walker {
             Self {
                 root: root.as_ref().into(),
                 matchers: Vec::from_iter(matchers),
+                excluded: Vec::new(),
+                ignore_file_name: None,
+                symlinks: Symlinks::default(),
             }
         }
+
+        pub fn symlinks(mut self, policy: Symlinks) -> Self {
+            self.symlinks = policy;
+            self
+        }
+
+        /// Skip files and whole directories matched by `matcher`.
+        pub fn exclude(mut self, matcher: Box<dyn m::Matcher>) -> Self {
+            self.excluded.push(matcher);
+            self
+        }
+
+        /// Skip files and whole directories matched by patterns in files of the given `name`
+        /// (typically ".backerignore"), found anywhere in the tree. The files use the syntax of
+        /// `.gitignore`, with the patterns relative to the directory of the file.
+        pub fn ignore_files(mut self, name: impl Into<String>) -> Self {
+            self.ignore_file_name = Some(name.into());
+            self
+        }
     }
 
     impl IntoIterator for Files {
         type Item = Result<DirEntry>;
         type IntoIter = FilesIterator;
         fn into_iter(self) -> Self::IntoIter {
+            let follow = self.symlinks == Symlinks::Follow;
             FilesIterator {
-                iter: WalkDir::new(&self.root).into_iter(),
+                iter: WalkDir::new(&self.root).follow_links(follow).into_iter(),
+                canonical_root: self.root.canonicalize().ok(),
                 files: self,
+                ignores: Vec::new(),
             }
         }
     }
@@ -129,6 +311,53 @@ pub mod walker {
     pub struct FilesIterator {
         files: Files,
         iter: walkdir::IntoIter,
+        /// Ignore files found in the directories we're currently inside, with their depth.
+        ignores: Vec<(usize, Gitignore)>,
+        /// Used to check that symbolic links don't lead outside the tree.
+        canonical_root: Option<PathBuf>,
+    }
+
+    impl FilesIterator {
+        /// If `path` resolves to a location inside the tree, returns it relative to the root.
+        fn resolve_inside_root(&self, path: &Path) -> Option<PathBuf> {
+            let root = self.canonical_root.as_ref()?;
+            let target = path.canonicalize().ok()?;
+            target.strip_prefix(root).ok().map(PathBuf::from)
+        }
+
+        fn is_excluded(&self, entry: &DirEntry, path: &Path) -> bool {
+            if self.files.excluded.iter().any(|m| m.matches(entry)) {
+                return true;
+            }
+            // The ignore file deepest in the tree has the final say.
+            for (_, ignore) in self.ignores.iter().rev() {
+                let found = ignore.matched(path, entry.is_dir);
+                if !found.is_none() {
+                    return found.is_ignore();
+                }
+            }
+            false
+        }
+
+        fn load_ignore_file(&mut self, dir: &Path, depth: usize) -> Result<()> {
+            let name = match &self.files.ignore_file_name {
+                Some(name) => name,
+                None => return Ok(()),
+            };
+            let path = dir.join(name);
+            if !path.is_file() {
+                return Ok(());
+            }
+            let mut builder = GitignoreBuilder::new(dir);
+            if let Some(err) = builder.add(&path) {
+                return Err(anyhow!("Failed to parse {:?}: {}", path, err));
+            }
+            let ignore = builder
+                .build()
+                .map_err(|err| anyhow!("Failed to parse {:?}: {}", path, err))?;
+            self.ignores.push((depth, ignore));
+            Ok(())
+        }
     }
 
     impl Iterator for FilesIterator {
@@ -138,18 +367,47 @@ pub mod walker {
                 // Basic iterator pass-through of errors & iteration end.
                 let entry = match self.iter.next() {
                     None => return None,
-                    Some(Err(err)) => return Some(Err(anyhow!(err))),
+                    Some(Err(err)) => {
+                        let path = err.path().map(PathBuf::from);
+                        let err = anyhow!(err);
+                        return Some(Err(match path {
+                            Some(path) => err.context(ErrorPath(path)),
+                            None => err,
+                        }));
+                    }
                     Some(Ok(entry)) => entry,
                 };
-                // Don't emit directory entries; emit error for symlinks.
+                // Forget ignore files from the directories we already left.
+                let depth = entry.depth();
+                while matches!(self.ignores.last(), Some((d, _)) if *d >= depth) {
+                    self.ignores.pop();
+                }
+                // Handle symlinks according to the policy.
                 let kind = entry.file_type();
-                if kind.is_dir() {
-                    continue;
-                } else if kind.is_symlink() {
-                    return Some(Err(anyhow!(
-                        "Don't know what to do with a symbolic link: {:?}",
-                        entry.path()
-                    )));
+                let mut alias_of = None;
+                if depth > 0 && entry.path_is_symlink() {
+                    match self.files.symlinks {
+                        Symlinks::Error => {
+                            return Some(Err(anyhow!(
+                                "Don't know what to do with a symbolic link: {:?}",
+                                entry.path()
+                            )
+                            .context(ErrorPath(entry.path().into()))))
+                        }
+                        Symlinks::Skip => continue,
+                        Symlinks::Follow => {
+                            if self.resolve_inside_root(entry.path()).is_none() {
+                                if kind.is_dir() {
+                                    self.iter.skip_current_dir();
+                                }
+                                continue;
+                            }
+                        }
+                        Symlinks::Alias => match self.resolve_inside_root(entry.path()) {
+                            Some(target) if entry.path().is_file() => alias_of = Some(target),
+                            _ => continue,
+                        },
+                    }
                 }
                 // Extract relative path.
                 // Note: walkdir pinky-promises that paths will have the prefix, so we should be
@@ -157,38 +415,184 @@ pub mod walker {
                 // squeeze another error there instead, just in case.
                 let relative_path = match entry.path().strip_prefix(&self.files.root) {
                     Err(err) => {
-                        return Some(Err(anyhow!("Failed to split relative path: {}", err)))
+                        return Some(Err(anyhow!("Failed to split relative path: {}", err)
+                            .context(ErrorPath(entry.path().into()))))
                     }
                     Ok(path) => path,
                 };
-                // Check if path is allowed by matchers.
-                let entry = DirEntry {
+                let dir_entry = DirEntry {
                     relative_path: relative_path.into(),
+                    is_dir: kind.is_dir(),
+                    alias_of,
                 };
-                {
-                    let mut matched = false;
-                    for m in &self.files.matchers {
-                        if m.matches(&entry) {
-                            matched = true;
-                            break;
-                        }
+                // Skip excluded entries, including whole subtrees of excluded directories.
+                if depth > 0 && self.is_excluded(&dir_entry, entry.path()) {
+                    if kind.is_dir() {
+                        self.iter.skip_current_dir();
                     }
-                    if !matched {
-                        continue;
+                    continue;
+                }
+                // Don't emit directory entries, but load their ignore files.
+                if kind.is_dir() {
+                    if let Err(err) = self.load_ignore_file(entry.path(), depth) {
+                        return Some(Err(err.context(ErrorPath(entry.path().into()))));
                     }
+                    continue;
                 }
-                return Some(Ok(entry));
+                // Check if path is allowed by matchers.
+                if !self.files.matchers.iter().any(|m| m.matches(&dir_entry)) {
+                    continue;
+                }
+                return Some(Ok(dir_entry));
             }
         }
     }
 
     #[cfg(test)]
     mod test {
+        use std::fs;
+
+        use tempfile::tempdir;
+
         use super::*;
 
         #[test]
         fn constructor() {
             let _ = Files::new(".", [m::CaseInsensitiveExtensions::boxed(["jpg", "jpeg"])]);
         }
+
+        fn touch(root: &Path, relative: &str) {
+            let path = root.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+
+        fn walk(files: Files) -> Vec<String> {
+            let mut found = files
+                .into_iter()
+                .map(|e| {
+                    let e = e.unwrap();
+                    let path = e.relative_path().to_string_lossy().replace('\\', "/");
+                    match e.alias_of() {
+                        Some(target) => format!("{} -> {}", path, target.display()),
+                        None => path,
+                    }
+                })
+                .collect::<Vec<_>>();
+            found.sort();
+            found
+        }
+
+        /// Creates a tree with symlinks inside `root`, and another directory they point into.
+        #[cfg(unix)]
+        fn tree_with_symlinks(root: &Path, outside: &Path) {
+            use std::os::unix::fs::symlink;
+
+            touch(root, "a.jpg");
+            touch(root, "sub/b.jpg");
+            touch(outside, "c.jpg");
+            symlink(root.join("a.jpg"), root.join("a-link.jpg")).unwrap();
+            symlink(root.join("sub"), root.join("sub-link")).unwrap();
+            symlink(outside.join("c.jpg"), root.join("c-link.jpg")).unwrap();
+            symlink(outside, root.join("outside-link")).unwrap();
+        }
+
+        #[cfg(unix)]
+        fn files_with_symlinks(root: &Path, policy: Symlinks) -> Files {
+            Files::new(root, [m::CaseInsensitiveExtensions::boxed(["jpg"])]).symlinks(policy)
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn symlinks_error() {
+            let (root, outside) = (tempdir().unwrap(), tempdir().unwrap());
+            tree_with_symlinks(root.path(), outside.path());
+            let files = files_with_symlinks(root.path(), Symlinks::Error);
+            let mut errors = files
+                .into_iter()
+                .filter_map(|e| e.err())
+                .map(|err| err.downcast_ref::<ErrorPath>().unwrap().0.clone())
+                .collect::<Vec<_>>();
+            errors.sort();
+            let links = ["a-link.jpg", "c-link.jpg", "outside-link", "sub-link"];
+            assert_eq!(errors, links.map(|l| root.path().join(l)));
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn symlinks_skip() {
+            let (root, outside) = (tempdir().unwrap(), tempdir().unwrap());
+            tree_with_symlinks(root.path(), outside.path());
+            let files = files_with_symlinks(root.path(), Symlinks::Skip);
+            assert_eq!(walk(files), vec!["a.jpg", "sub/b.jpg"]);
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn symlinks_follow() {
+            let (root, outside) = (tempdir().unwrap(), tempdir().unwrap());
+            tree_with_symlinks(root.path(), outside.path());
+            let files = files_with_symlinks(root.path(), Symlinks::Follow);
+            assert_eq!(
+                walk(files),
+                vec!["a-link.jpg", "a.jpg", "sub-link/b.jpg", "sub/b.jpg"]
+            );
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn symlinks_follow_loop() {
+            let root = tempdir().unwrap();
+            touch(root.path(), "sub/a.jpg");
+            std::os::unix::fs::symlink(root.path(), root.path().join("sub/loop")).unwrap();
+            let files = files_with_symlinks(root.path(), Symlinks::Follow);
+            let (ok, err): (Vec<_>, Vec<_>) = files.into_iter().partition(|e| e.is_ok());
+            assert_eq!(ok.len(), 1);
+            assert_eq!(err.len(), 1);
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn symlinks_alias() {
+            let (root, outside) = (tempdir().unwrap(), tempdir().unwrap());
+            tree_with_symlinks(root.path(), outside.path());
+            let files = files_with_symlinks(root.path(), Symlinks::Alias);
+            assert_eq!(
+                walk(files),
+                vec!["a-link.jpg -> a.jpg", "a.jpg", "sub/b.jpg"]
+            );
+        }
+
+        #[test]
+        fn excludes_and_ignore_files() {
+            let root = tempdir().unwrap();
+            let root = root.path();
+            for f in [
+                "a.jpg",
+                "b.png",
+                "@eaDir/a.jpg",
+                "foo/@eaDir/a.jpg",
+                "foo/b.jpg",
+                "foo/c.jpg",
+                "foo/keep/c.jpg",
+                "foo/cache/d.jpg",
+                "bar/c.jpg",
+            ] {
+                touch(root, f);
+            }
+            fs::write(
+                root.join("foo/.backerignore"),
+                "c.jpg\n!keep/c.jpg\ncache/\n",
+            )
+            .unwrap();
+
+            let files = Files::new(root, [m::CaseInsensitiveExtensions::boxed(["jpg"])])
+                .exclude(m::Globs::new(["**/@eaDir"]).unwrap().boxed())
+                .ignore_files(".backerignore");
+            assert_eq!(
+                walk(files),
+                vec!["a.jpg", "bar/c.jpg", "foo/b.jpg", "foo/keep/c.jpg"]
+            );
+        }
     }
 }
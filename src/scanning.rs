@@ -1,11 +1,13 @@
-use std::fs::{self, File};
-use std::io::{self, Write};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Condvar;
 
 use anyhow::{Context, Result};
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset};
 use exif::{Exif, Reader as ExifReader};
-use image::imageops::FilterType;
 use image::io::Reader as ImageReader;
 use path_slash::{PathBufExt, PathExt};
 use rayon::prelude::*;
@@ -13,36 +15,243 @@ use rusqlite::Connection as DbConnection;
 use sha1::{Digest, Sha1};
 use thiserror::Error;
 
-use crate::config::{self, Config, DatePath};
+use crate::config::{Config, DatePath, Discover, FileDate, IgnoreSmall};
 use crate::db::{self, SyncedDb};
+use crate::geocoding::Places;
+use crate::hashing;
 use crate::imaging::*;
 use crate::interlude::*;
-use crate::model;
+use crate::isobmff;
+use crate::marker::{self, Marker};
+use crate::model::{self, DateSource, HashAlgorithm, ProblemKind, ThumbnailSource};
+use crate::pathwalk::walker::Symlinks;
 use crate::pathwalk::{matcher, walker};
 
+const JPEG_EXTENSIONS: [&str; 2] = ["jpg", "jpeg"];
+/// Camera RAW formats which are based on TIFF, so we can find an embedded JPEG preview in them.
+pub(crate) const RAW_EXTENSIONS: [&str; 4] = ["cr2", "nef", "arw", "dng"];
+/// Video formats based on the ISO base media file format.
+const VIDEO_EXTENSIONS: [&str; 3] = ["mp4", "mov", "3gp"];
+/// Name of files with `.gitignore`-like patterns of paths to skip when scanning a tree.
+pub const IGNORE_FILE_NAME: &str = ".backerignore";
+
 pub fn scan(db: SyncedDb, config: Config) -> Result<()> {
-    let date_paths = config.date_path;
-    for err in config
-        .markers
-        .disk
-        .into_par_iter()
-        .enumerate()
-        .filter_map(|(i, marker)| process_tree(i, marker, date_paths.clone(), db.clone()).err())
-        .collect::<Vec<_>>()
-    {
-        ieprintln!("Error: " err);
+    let places = config.geocoding.as_ref().map(Places::load).transpose()?;
+    let markers = config.markers.for_current_host();
+    for (path, reason) in &markers.not_applicable {
+        iprintln!("\nSkipping marker " path;? ": " reason);
+    }
+    let mut marker_paths = markers.disk;
+    if let Some(discover) = &config.markers.discover {
+        let found = discover_markers(discover, &marker_paths, &config, &db)?;
+        marker_paths.extend(found);
     }
+    let in_flight = InFlight::new(config.max_in_flight_mb.map(|mb| mb * 1024 * 1024));
+    let scanning = AtomicBool::new(true);
+    std::thread::scope(|s| {
+        let worker = config
+            .defer_thumbnails
+            .then(|| s.spawn(|| make_pending_thumbnails(&db, &in_flight, &scanning)));
+        for err in marker_paths
+            .par_iter()
+            .enumerate()
+            .filter_map(|(i, marker)| {
+                process_tree(i, marker, &config, places.as_ref(), db.clone(), &in_flight).err()
+            })
+            .collect::<Vec<_>>()
+        {
+            ieprintln!("Error: " err);
+        }
+        scanning.store(false, Ordering::Release);
+        match worker {
+            Some(worker) => {
+                iprintln!("\nMaking pending thumbnails...");
+                worker.join().unwrap()
+            }
+            None => Ok(()),
+        }
+    })
+}
 
+/// Makes thumbnails of files which were cataloged without them (see: [Config::defer_thumbnails]),
+/// until none are left and `scanning` is over. It runs in a single thread, taking the DB only
+/// briefly, so that it doesn't hold up scanning the trees, which runs in parallel. Files which
+/// can't be read or decoded now are left for the next scan.
+pub fn make_pending_thumbnails(
+    db: &SyncedDb,
+    in_flight: &InFlight,
+    scanning: &AtomicBool,
+) -> Result<()> {
+    let mut tried = HashSet::new();
+    let mut made = HashSet::new();
+    loop {
+        // Checked before querying, so that files added at the end of scanning are not missed.
+        let still_scanning = scanning.load(Ordering::Acquire);
+        let files = db::pending_thumbnails(&db.lock().unwrap())
+            .run(())
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let files = files
+            .into_iter()
+            .filter(|(hash, marker, _, relative, _)| {
+                !made.contains(hash) && !tried.contains(&(marker.clone(), relative.clone()))
+            })
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            if !still_scanning {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            continue;
+        }
+        for (hash, marker, root, relative, orientation) in files {
+            // Any of the file's locations will do.
+            if made.contains(&hash) {
+                continue;
+            }
+            let path = Path::new(&root).join(PathBuf::from_slash(&relative));
+            tried.insert((marker.clone(), relative.clone()));
+            // Files which can't be read, or have no preview, are already recorded by scanning.
+            let len = fs::metadata(&path).map_or(0, |m| m.len());
+            let _permit = in_flight.acquire(len);
+            let Ok(buf) = fs::read(&path) else {
+                continue;
+            };
+            let exif = ExifReader::new()
+                .read_from_container(&mut io::Cursor::new(&buf))
+                .ok();
+            let is_raw = has_extension(&relative, &RAW_EXTENSIONS);
+            let Some(image_buf) = image_data(&buf, exif.as_ref(), is_raw) else {
+                continue;
+            };
+            let orientation = orientation
+                .and_then(Orientation::from_exif)
+                .unwrap_or(Orientation::Normal);
+            let thumb = match encode_thumbnail(
+                image_buf,
+                exif.as_ref(),
+                orientation,
+                THUMBNAIL_SIZE,
+            ) {
+                Ok(thumb) => thumb,
+                Err(err) => {
+                    ieprintln!("\nFailed to decode JPEG " &path;? ", leaving thumbnail pending: " err);
+                    let db = db.lock().unwrap();
+                    let message = err.to_string();
+                    db::add_scan_problem(&db, &marker, &relative, ProblemKind::Decode, &message)?;
+                    continue;
+                }
+            };
+            let camera = camera_of(exif.as_ref(), thumb.full_size, is_raw);
+            let db = db.lock().unwrap();
+            db::set_made_thumbnail(&db, &hash, &thumb.jpeg, thumb.source, thumb.phash)?;
+            db::set_camera(&db, &hash, &camera)?;
+            made.insert(hash);
+        }
+    }
+    if !made.is_empty() {
+        iprintln!("\nMade " made.len() " pending thumbnails.");
+    }
     Ok(())
 }
 
+/// Corrects orientation of thumbnails created before they were deoriented, reading just the Exif
+/// block of the original files. It's a one-off fix-up of old catalogs, run by the `reorient`
+/// command; scanning a tree records orientation of its files anyway. Files which can't be read
+/// now, e.g. because their tree is not mounted, are left for a later run.
+pub fn reorient_thumbnails(db: &SyncedDb) -> Result<()> {
+    let files = db::unoriented_files(&db.lock().unwrap())
+        .run(())
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut done: Option<String> = None;
+    let mut rotated = 0;
+    for (hash, root, relative, thumb) in files {
+        // Any of the file's locations will do.
+        if done.as_ref() == Some(&hash) {
+            continue;
+        }
+        let path = Path::new(&root).join(PathBuf::from_slash(&relative));
+        let Ok(file) = fs::File::open(&path) else {
+            continue;
+        };
+        let orientation = ExifReader::new()
+            .read_from_container(&mut io::BufReader::new(file))
+            .ok()
+            .and_then(|e| e.orientation())
+            .unwrap_or(Orientation::Normal);
+        if orientation == Orientation::Normal {
+            db::set_orientation(&db.lock().unwrap(), &hash, orientation)?;
+        } else {
+            let img = match image::load_from_memory(&thumb) {
+                Ok(img) => img,
+                Err(err) => {
+                    ieprintln!("\nFailed to decode thumbnail of " &path;? ", skipping: " err);
+                    continue;
+                }
+            };
+            let img = orientation.deorient(img);
+            let mut thumb_jpeg = Vec::<u8>::new();
+            img.write_to(&mut thumb_jpeg, image::ImageOutputFormat::Jpeg(90))?;
+            let db_writable = db.lock().unwrap();
+            db::set_oriented_thumbnail(&db_writable, &hash, &thumb_jpeg, orientation)?;
+            db::set_perceptual_hash(&db_writable, &hash, Some(dhash(&img)))?;
+            rotated += 1;
+        }
+        done = Some(hash);
+    }
+    if rotated > 0 {
+        iprintln!("\nCorrected orientation of " rotated " thumbnails.");
+    }
+    Ok(())
+}
+
+/// Finds marker files of known trees on mounted volumes, skipping ones already `listed`. A tree is
+/// known if it's mentioned in the config, or if it has files in the catalog.
+fn discover_markers(
+    discover: &Discover,
+    listed: &[PathBuf],
+    config: &Config,
+    db: &SyncedDb,
+) -> Result<Vec<PathBuf>> {
+    let mut known = db::known_markers(&db.lock().unwrap())?;
+    known.extend(config.date_path.keys().cloned());
+    known.extend(config.marker.keys().cloned());
+    let listed = listed
+        .iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .collect::<Vec<_>>();
+
+    let mut found = Vec::new();
+    for path in marker::discover(discover) {
+        let id = match marker::read(&path) {
+            Ok((_, marker)) => marker.id,
+            Err(err) => {
+                ieprintln!("\nSkipping discovered marker " path;? ": " error_chain(&err));
+                continue;
+            }
+        };
+        if !known.contains(&id) {
+            iprintln!("\nSkipping discovered marker of unknown tree " id;? " at: " path;?);
+            continue;
+        }
+        if path.canonicalize().is_ok_and(|p| listed.contains(&p)) {
+            continue;
+        }
+        iprintln!("\nDiscovered marker " id;? " at: " path;?);
+        found.push(path);
+    }
+    Ok(found)
+}
+
 pub fn process_tree(
     i: usize,
     marker_path: impl AsRef<Path>,
-    date_paths_per_marker: config::DatePathsPerMarker,
+    config: &Config,
+    places: Option<&Places>,
     db: Arc<Mutex<DbConnection>>,
+    in_flight: &InFlight,
 ) -> Result<()> {
-    let m = Tree::open(marker_path, &date_paths_per_marker);
+    let marker_path = marker_path.as_ref();
+    let m = Tree::open(marker_path, config);
     if let Err(TreeError::NotFound { .. }) = &m {
         iprintln!("\nSkipping tree: " error_chain(&m.unwrap_err().into()));
         return Ok(());
@@ -50,17 +259,24 @@ pub fn process_tree(
     let tree: Tree = m?;
     iprintln!("marker " &tree.marker " at: " tree.root;?);
 
+    // Refuse to merge catalog rows of two different trees claiming the same marker id.
+    marker::check_identity(&db.lock().unwrap(), marker_path, &tree.info)?;
+
     // Match any date-path config to marker.
     iprintln!("\nDate-paths at " tree.marker;? ": " tree.date_paths;?);
 
+    // Files skipped or failed in previous scans will get re-evaluated.
+    db::clear_skipped(&db.lock().unwrap(), &tree.marker)?;
+    db::clear_scan_problems(&db.lock().unwrap(), &tree.marker)?;
+
     // Stage 1: add not-yet-known files into DB
-    stage1(i, &tree, &db, OnExisting::Skip)?;
+    stage1(i, &tree, places, &db, in_flight, OnExisting::Skip)?;
 
     // Stage 2: check if all files from DB are present on disk, delete entries for any missing
     stage2(&tree, &db)?;
 
     // Stage 3: scan all files once more and refresh them in DB
-    stage1(i, &tree, &db, OnExisting::Refresh)?;
+    stage1(i, &tree, places, &db, in_flight, OnExisting::Refresh)?;
 
     Ok(())
 }
@@ -74,16 +290,33 @@ enum OnExisting {
 fn stage1(
     i: usize,
     tree: &Tree,
+    places: Option<&Places>,
     db: &Arc<Mutex<DbConnection>>,
+    in_flight: &InFlight,
     on_existing: OnExisting,
 ) -> Result<()> {
+    let raw_matcher = matcher::CaseInsensitiveExtensions::boxed(RAW_EXTENSIONS);
+    let video_matcher = matcher::CaseInsensitiveExtensions::boxed(VIDEO_EXTENSIONS);
+    // Files which can't be cataloged are recorded, so that they can be reviewed and fixed.
+    let problem = |relative: &str, kind, message: &str| {
+        db::add_scan_problem(&db.lock().unwrap(), &tree.marker, relative, kind, message)
+    };
+    let read_problem = |path: &Path, relative: &str, err: io::Error| {
+        ieprintln!("\nFailed to read file " path;? ", skipping: " err);
+        problem(relative, ProblemKind::Read, &err.to_string())
+    };
     // TODO[LATER]: in parallel thread, count all matching files, then when done start showing progress bar/percentage
     for entry in tree.iter() {
         let entry = match entry {
             // TODO[LATER]: use `let else` once stable
             Ok(entry) => entry,
             Err(err) => {
-                ieprintln!("\nFailed to access file, skipping: " err);
+                ieprintln!("\nFailed to access file, skipping: " error_chain(&err));
+                let relative = err
+                    .downcast_ref::<walker::ErrorPath>()
+                    .and_then(|p| p.0.strip_prefix(&tree.root).ok()?.to_slash())
+                    .unwrap_or_default();
+                problem(&relative, ProblemKind::Access, &error_chain(&err))?;
                 continue;
             }
         };
@@ -93,9 +326,6 @@ fn stage1(
             .to_slash()
             .with_context(|| ifmt!("Failed to convert path " os_relative;? " to slash-based"))?;
 
-        // Read file contents to memory.
-        let buf = fs::read(&path)?;
-
         // If file already exists in DB, skip it.
         let db_readable = db.lock().unwrap();
         if db::exists(&db_readable, &tree.marker, &relative)? && on_existing == OnExisting::Skip {
@@ -105,48 +335,221 @@ fn stage1(
         }
         drop(db_readable);
 
-        // Calculate sha1 hash of the file contents.
-        // TODO[LATER]: maybe switch to a secure hash (sha2 or other, see: https://github.com/RustCrypto/hashes)
-        let hash = hash(&buf);
-        // let hash = format!("{:x}", Sha1::digest(&buf));
-
-        // FIXME: if image is very small, it's probably a thumbnail already and we don't want to archive it
-
-        // Does the JPEG have Exif block? We assume it'd be the most reliable source of metadata.
-        let exif = ExifReader::new()
-            .read_from_container(&mut io::Cursor::new(&buf))
-            .ok();
-        let date = try_deduce_date(exif.as_ref(), &relative, tree.date_paths.iter());
-        // // TODO[LATER]: use some orientation enum / stricter type instead of raw u16
-        // let orientation = exif.as_ref().and_then(|v| v.orientation()).unwrap_or(1);
-
-        // Parse the file as an image and create thumbnail, or skip with warning if impossible.
-        let img = match ImageReader::new(io::Cursor::new(&buf))
-            .with_guessed_format()?
-            .decode()
-        {
-            Ok(img) => img,
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                read_problem(&path, &relative, err)?;
+                continue;
+            }
+        };
+        let len = metadata.len();
+
+        // Tiny images are most probably thumbnails already, which we don't want to archive.
+        let is_raw = raw_matcher.matches(&entry);
+        let is_video = video_matcher.matches(&entry);
+        if let (false, false, Some(limits)) = (is_raw, is_video, &tree.ignore_small) {
+            if let Some(reason) = check_small(limits, &path, len) {
+                db::skip(&db.lock().unwrap(), &tree.marker, &relative, &reason)?;
+                print!("s");
+                io::stdout().flush()?;
+                continue;
+            }
+        }
+
+        // Images are read whole into memory for decoding, within the limit of bytes in flight.
+        // Other files are only streamed, as are JPEGs with deferred thumbnails, as just their
+        // Exif block is needed (RAW files keep their preview in it).
+        let whole = !is_video && (is_raw || !tree.defer_thumbnails);
+        let _permit = whole.then(|| in_flight.acquire(len));
+        let buf = match whole.then(|| fs::read(&path)).transpose() {
+            Ok(buf) => buf,
             Err(err) => {
-                // TODO[LATER]: use termcolor crate to print errors in red
-                // FIXME[LATER]: resolve JPEG decoding error: "spectral selection is not allowed in non-progressive scan"
-                ieprintln!("\nFailed to decode JPEG " &path;? ", skipping: " err);
+                read_problem(&path, &relative, err)?;
                 continue;
             }
         };
-        // let thumb = img.resize(200, 200, FilterType::Lanczos3);
-        let thumb = img.resize(200, 200, FilterType::CatmullRom);
-        // FIXME[LATER]: fix the thumbnail's orientation
-        let mut thumb_jpeg = Vec::<u8>::new();
-        thumb.write_to(&mut thumb_jpeg, image::ImageOutputFormat::Jpeg(90))?;
 
-        // Add image entry to DB.
+        // Calculate digests of the file contents; the SHA-1 one is its hash, identifying the file.
+        let digests = match &buf {
+            Some(buf) => Ok(hashing::digests(buf, &tree.hash_algorithms)),
+            None => hashing::digests_of_file(&path, &tree.hash_algorithms),
+        };
+        let (hash, digests) = match digests {
+            Ok(mut digests) => (digests.remove(0).1, digests),
+            Err(err) => {
+                read_problem(&path, &relative, err)?;
+                continue;
+            }
+        };
+
+        let (date, thumb_jpeg, thumb_source, media, gps, camera, orientation, phash) = if is_video {
+            // We don't decode videos (yet), so they only get a placeholder thumbnail.
+            let date = fs::File::open(&path)
+                .and_then(|file| isobmff::creation_time_from(BufReader::new(file)))
+                .ok()
+                .flatten()
+                .map(|utc| match tree.timezone {
+                    // Container times are in UTC, but we want to show the local time.
+                    Some(offset) => {
+                        let local = utc + Duration::seconds(offset.local_minus_utc().into());
+                        (local, Some(offset), DateSource::Container)
+                    }
+                    // With the offset unknown, the UTC time is the best local approximation.
+                    None => (utc, None, DateSource::Container),
+                })
+                .or_else(|| try_deduce_date(None, &[], &relative, tree.date_paths.iter()));
+            (
+                date,
+                video_placeholder()?,
+                ThumbnailSource::Placeholder,
+                model::MediaType::Video,
+                None,
+                model::Camera::default(),
+                Orientation::Normal,
+                None,
+            )
+        } else {
+            // Does the JPEG have Exif block? We assume it'd be the most reliable source of metadata.
+            let exif = match &buf {
+                Some(buf) => ExifReader::new()
+                    .read_from_container(&mut io::Cursor::new(buf))
+                    .ok(),
+                None => read_exif(&path),
+            };
+            let date = try_deduce_date(
+                exif.as_ref(),
+                &tree.date_tags,
+                &relative,
+                tree.date_paths.iter(),
+            );
+            // Camera clocks are usually in local time, while GPS time is always in UTC.
+            let date = match (date, exif.as_ref().and_then(|e| e.gps_datetime())) {
+                (Some((local, None, DateSource::Exif)), Some(utc)) => {
+                    Some((local, offset_from_gps(local, utc), DateSource::Exif))
+                }
+                (date, _) => date,
+            };
+            let orientation = exif
+                .as_ref()
+                .and_then(|e| e.orientation())
+                .unwrap_or(Orientation::Normal);
+
+            let image_buf = match buf.as_deref().map(|b| image_data(b, exif.as_ref(), is_raw)) {
+                Some(Some(image_buf)) => Some(image_buf),
+                Some(None) => {
+                    ieprintln!("\nNo JPEG preview found in RAW file " &path;? ", skipping");
+                    problem(&relative, ProblemKind::NoPreview, "no JPEG preview found")?;
+                    continue;
+                }
+                None => None,
+            };
+            let gps = exif.as_ref().and_then(|e| e.gps_position());
+
+            let image_buf = image_buf.filter(|_| !tree.defer_thumbnails);
+            if let Some(image_buf) = image_buf {
+                // Parse the file as an image and create thumbnail, or skip with warning if impossible.
+                let thumb =
+                    match encode_thumbnail(image_buf, exif.as_ref(), orientation, THUMBNAIL_SIZE) {
+                        Ok(thumb) => thumb,
+                        Err(err) => {
+                            // TODO[LATER]: use termcolor crate to print errors in red
+                            ieprintln!("\nFailed to decode JPEG " &path;? ", skipping: " err);
+                            problem(&relative, ProblemKind::Decode, &err.to_string())?;
+                            continue;
+                        }
+                    };
+                (
+                    date,
+                    thumb.jpeg,
+                    thumb.source,
+                    model::MediaType::Image,
+                    gps,
+                    camera_of(exif.as_ref(), thumb.full_size, is_raw),
+                    orientation,
+                    Some(thumb.phash),
+                )
+            } else {
+                (
+                    date,
+                    Vec::new(),
+                    ThumbnailSource::Pending,
+                    model::MediaType::Image,
+                    gps,
+                    camera_of(exif.as_ref(), None, is_raw),
+                    orientation,
+                    None,
+                )
+            }
+        };
+
+        // As the last resort, if enabled, use a date from the filesystem.
+        let date = match (date, tree.file_date) {
+            (None, Some(file_date)) => filesystem_date(&metadata, file_date).map(|d| {
+                (
+                    d.naive_local(),
+                    Some(d.offset().fix()),
+                    DateSource::Filesystem,
+                )
+            }),
+            (date, _) => date,
+        };
+        // If the timezone is unknown, the tree's default one is assumed.
+        let date = date.map(|(d, offset, source)| (d, offset.or(tree.timezone), source));
+
+        // Add file entry to DB.
         let info = model::FileInfo {
             hash: hash.clone(),
-            date,
+            date: date.map(|(d, _, _)| d),
+            date_offset: date.and_then(|(_, offset, _)| offset),
+            date_source: date.map(|(_, _, source)| source),
             thumb: thumb_jpeg,
+            media,
         };
         let db_writable = db.lock().unwrap();
         db::upsert(&db_writable, &tree.marker, &relative, &info)?;
+        db::set_orientation(&db_writable, &hash, orientation)?;
+        for (algorithm, digest) in &digests {
+            db::set_digest(&db_writable, &hash, *algorithm, digest)?;
+        }
+        if thumb_source == ThumbnailSource::Pending {
+            db::set_thumbnail_pending(&db_writable, &hash)?;
+        } else {
+            db::set_perceptual_hash(&db_writable, &hash, phash)?;
+            db::set_thumbnail_source(&db_writable, &hash, thumb_source)?;
+        }
+        db::set_gps(&db_writable, &hash, gps.as_ref())?;
+        db::set_camera(&db_writable, &hash, &camera)?;
+        if let (Some(places), Some(gps)) = (places, &gps) {
+            db::set_place(&db_writable, &hash, places.nearest(gps))?;
+        }
+        // Symlinks are marked, so that they don't look like extra copies of the file.
+        let alias_of =
+            match entry.alias_of() {
+                Some(target) => Some(target.to_slash().with_context(
+                    || ifmt!("Failed to convert path " target;? " to slash-based"),
+                )?),
+                None => None,
+            };
+        db::set_alias_of(&db_writable, &tree.marker, &relative, alias_of.as_deref())?;
+        // Link RAW+JPEG pairs shot together, so that they're treated as a single asset.
+        // Whichever of the pair gets scanned later, makes the link.
+        if is_raw {
+            let jpegs = siblings(&relative, &JPEG_EXTENSIONS);
+            db::pair_raw_with_jpeg(
+                &db_writable,
+                &tree.marker,
+                std::slice::from_ref(&relative),
+                &jpegs,
+            )?;
+        } else if media == model::MediaType::Image {
+            let raws = siblings(&relative, &RAW_EXTENSIONS);
+            db::pair_raw_with_jpeg(
+                &db_writable,
+                &tree.marker,
+                &raws,
+                std::slice::from_ref(&relative),
+            )?;
+        }
         drop(db_writable);
 
         // Print some debugging info, showing which marker is still being processed.
@@ -164,20 +567,21 @@ pub fn stage2(tree: &Tree, db: &Arc<Mutex<DbConnection>>) -> Result<()> {
 
         let path = tree.root.join(PathBuf::from_slash(&relative_path));
 
-        // Try reading file contents to memory.
-        let contents = match fs::read(&path) {
-            Ok(data) => Some(data),
+        // Verify the file with the strongest of its digests.
+        let (algorithm, db_digest) = hashing::strongest_digest(&db.lock().unwrap(), &db_hash)?;
+
+        // Try hashing file contents, streamed so that big files are not held in memory.
+        let disk_digest = match hashing::digests_of_file(&path, &[algorithm]) {
+            Ok(mut digests) => Some(digests.remove(0).1),
             Err(err) if err.kind() == io::ErrorKind::NotFound => None,
             Err(err) => return Err(anyhow!(err)),
         };
-        if let Some(data) = contents {
-            let disk_hash = hash(&data);
-
-            if disk_hash == db_hash {
+        if let Some(disk_digest) = disk_digest {
+            if disk_digest == db_digest {
                 print!(",");
                 io::stdout().flush()?;
             } else {
-                iprintln!("\nBAD HASH: " disk_hash " != " db_hash " @ " path;?);
+                iprintln!("\nBAD HASH: " disk_digest " != " db_digest " (" algorithm;? ") @ " path;?);
                 // iprintln!("* " db_hash " @ " path;?);
             }
         } else {
@@ -192,9 +596,23 @@ pub fn stage2(tree: &Tree, db: &Arc<Mutex<DbConnection>>) -> Result<()> {
 
 #[derive(Clone, Debug)]
 pub struct Tree {
+    /// Id of the marker.
     pub marker: String,
+    /// Full contents of the marker file.
+    pub info: Marker,
     pub root: PathBuf,
     pub date_paths: Vec<DatePath>,
+    pub ignore_small: Option<IgnoreSmall>,
+    pub exclude: matcher::Globs,
+    pub symlinks: Symlinks,
+    pub file_date: Option<FileDate>,
+    pub date_tags: Vec<DateTag>,
+    /// Default offset from UTC of dates in the tree.
+    pub timezone: Option<FixedOffset>,
+    /// If set, files are cataloged with pending thumbnails (see: [make_pending_thumbnails]).
+    pub defer_thumbnails: bool,
+    /// Algorithms of digests calculated for files, starting with SHA-1.
+    pub hash_algorithms: Vec<HashAlgorithm>,
 }
 
 #[derive(Error, Debug)]
@@ -209,11 +627,8 @@ pub enum TreeError {
 }
 
 impl Tree {
-    pub fn open(
-        marker_path: impl AsRef<Path>,
-        date_paths_per_marker: &config::DatePathsPerMarker,
-    ) -> Result<Tree, TreeError> {
-        let (root, marker) = match marker_read(marker_path.as_ref()) {
+    pub fn open(marker_path: impl AsRef<Path>, config: &Config) -> Result<Tree, TreeError> {
+        let (root, info) = match marker::read(marker_path.as_ref()) {
             Err(err)
                 if err.downcast_ref().map(io::Error::kind) == Some(io::ErrorKind::NotFound) =>
             {
@@ -227,63 +642,220 @@ impl Tree {
             }
             Ok(tree) => tree,
         };
-        let date_paths = date_paths_per_marker.get(&marker);
+        let marker = info.id.clone();
+        let date_paths = config.date_path.get(&marker);
         let date_paths = date_paths.map(|v| v.to_owned()).unwrap_or_default();
+        let ignore_small = config.ignore_small_for(&marker).cloned();
+        let symlinks = config.symlinks_for(&marker);
+        let file_date = config.file_date_for(&marker);
+        let date_tags = config.date_tags_for(&marker).to_vec();
+        let timezone = config.timezone_for(&marker);
+        let exclude =
+            matcher::Globs::new(config.exclude_for(&marker)).map_err(|err| TreeError::Other {
+                path: marker_path.as_ref().to_owned(),
+                source: anyhow!(err).context("invalid exclude pattern in config"),
+            })?;
         Ok(Tree {
             marker,
+            info,
             root,
             date_paths,
+            ignore_small,
+            exclude,
+            symlinks,
+            file_date,
+            date_tags,
+            timezone,
+            defer_thumbnails: config.defer_thumbnails,
+            hash_algorithms: config.hash_algorithms(),
         })
     }
 
     pub fn iter(&self) -> walker::FilesIterator {
-        let jpeg_matcher = matcher::CaseInsensitiveExtensions::boxed(["jpg", "jpeg"]);
-        walker::Files::new(&self.root, [jpeg_matcher]).into_iter()
+        let jpeg_matcher = matcher::CaseInsensitiveExtensions::boxed(JPEG_EXTENSIONS);
+        let raw_matcher = matcher::CaseInsensitiveExtensions::boxed(RAW_EXTENSIONS);
+        let video_matcher = matcher::CaseInsensitiveExtensions::boxed(VIDEO_EXTENSIONS);
+        let matchers = [jpeg_matcher, raw_matcher, video_matcher];
+        walker::Files::new(&self.root, matchers)
+            .exclude(self.exclude.clone().boxed())
+            .ignore_files(IGNORE_FILE_NAME)
+            .symlinks(self.symlinks)
+            .into_iter()
     }
 }
 
-// TODO[LATER]: accept Path and return Result<(Path,...)> with proper lifetime
-fn marker_read(file_path: &Path) -> Result<(PathBuf, String)> {
-    let parent = file_path.parent().ok_or_else(|| {
-        anyhow!(
-            "Could not split parent directory of '{}'",
-            file_path.display()
-        )
-    })?;
+/// Returns the image data of a file, i.e. the file itself, or for RAW files the JPEG preview
+/// embedded by the camera, as we don't try to demosaic them; `None` if there's no preview.
+pub(crate) fn image_data<'a>(
+    buf: &'a [u8],
+    exif: Option<&'a Exif>,
+    is_raw: bool,
+) -> Option<&'a [u8]> {
+    if is_raw {
+        exif.and_then(|e| e.embedded_jpeg())
+    } else {
+        Some(buf)
+    }
+}
+
+/// Thumbnail of a photo, deoriented and encoded as JPEG for storing in the DB.
+pub(crate) struct EncodedThumbnail {
+    pub jpeg: Vec<u8>,
+    pub source: ThumbnailSource,
+    pub phash: u64,
+    pub full_size: Option<(u32, u32)>,
+}
 
-    use serde::Deserialize;
-    #[derive(Deserialize)]
-    struct Marker {
-        id: String,
+pub(crate) fn encode_thumbnail(
+    image_buf: &[u8],
+    exif: Option<&Exif>,
+    orientation: Orientation,
+    size: u32,
+) -> image::ImageResult<EncodedThumbnail> {
+    let thumbnail = thumbnail(image_buf, exif, size)?;
+    let thumb = orientation.deorient(thumbnail.image);
+    let mut jpeg = Vec::<u8>::new();
+    thumb.write_to(&mut jpeg, image::ImageOutputFormat::Jpeg(90))?;
+    Ok(EncodedThumbnail {
+        jpeg,
+        source: thumbnail.source,
+        phash: dhash(&thumb),
+        full_size: thumbnail.full_size,
+    })
+}
+
+/// Camera settings from Exif, with the size of the photo taken from `full_size` of the decoded
+/// image if missing there.
+fn camera_of(exif: Option<&Exif>, full_size: Option<(u32, u32)>, is_raw: bool) -> model::Camera {
+    let mut camera = exif.map(|e| e.camera()).unwrap_or_default();
+    // A RAW file's preview may be smaller than the photo, so its size is not reliable.
+    let full_size = full_size.filter(|_| !is_raw);
+    let exif_size = camera.width.is_some() && camera.height.is_some();
+    if let Some((width, height)) = full_size.filter(|_| !exif_size) {
+        camera.width = Some(width);
+        camera.height = Some(height);
     }
-    let file = File::open(file_path)
-        .with_context(|| format!("Failed to open '{}'", file_path.display()))?;
-    let m: Marker = serde_json::from_reader(io::BufReader::new(file))?;
+    camera
+}
 
-    Ok((parent.to_owned(), m.id))
+pub(crate) fn has_extension(relative: &str, extensions: &[&str]) -> bool {
+    Path::new(relative)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
 }
 
 /// Calculate a hash of the buf contents, and return it in a pretty-printed format for storing in
-/// the DB.
+/// the DB. It's SHA-1, which identifies files in the catalog; see [hashing] for stronger digests.
 pub fn hash(buf: &[u8]) -> String {
-    // TODO[LATER]: maybe switch to a secure hash (sha2 or other, see: https://github.com/RustCrypto/hashes)
     format!("{:x}", Sha1::digest(buf))
 }
 
+/// Exif data of the file at `path`, reading only as much of it as needed to find the Exif block.
+fn read_exif(path: &Path) -> Option<Exif> {
+    let file = fs::File::open(path).ok()?;
+    ExifReader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()
+}
+
+/// Checks if the image at `path`, of `len` bytes, is below the size `limits`, reading only its
+/// header to find out the dimensions. Returns the reason to skip it if so.
+fn check_small(limits: &IgnoreSmall, path: &Path, len: u64) -> Option<String> {
+    let dimensions = fs::File::open(path)
+        .ok()
+        .and_then(|file| {
+            ImageReader::new(BufReader::new(file))
+                .with_guessed_format()
+                .ok()
+        })
+        .and_then(|reader| reader.into_dimensions().ok());
+    if !limits.is_small(len, dimensions) {
+        return None;
+    }
+    Some(match dimensions {
+        Some((w, h)) => ifmt!("small image: " w "x" h ", " len " bytes"),
+        None => ifmt!("small image: " len " bytes"),
+    })
+}
+
+/// Limit of bytes of file contents held in memory at once, shared by all threads of a scan (see:
+/// [Config::max_in_flight_mb]).
+#[derive(Default)]
+pub struct InFlight {
+    /// Unlimited if not set.
+    limit: Option<u64>,
+    used: Mutex<u64>,
+    freed: Condvar,
+}
+
+impl InFlight {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Waits until `bytes` more fit in the limit, and holds them until the permit is dropped. A
+    /// file bigger than the whole limit is let through when nothing else is in flight.
+    pub fn acquire(&self, bytes: u64) -> InFlightPermit<'_> {
+        let mut used = self.used.lock().unwrap();
+        if let Some(limit) = self.limit {
+            while *used > 0 && *used + bytes > limit {
+                used = self.freed.wait(used).unwrap();
+            }
+        }
+        *used += bytes;
+        InFlightPermit {
+            in_flight: self,
+            bytes,
+        }
+    }
+}
+
+pub struct InFlightPermit<'a> {
+    in_flight: &'a InFlight,
+    bytes: u64,
+}
+
+impl Drop for InFlightPermit<'_> {
+    fn drop(&mut self) {
+        *self.in_flight.used.lock().unwrap() -= self.bytes;
+        self.in_flight.freed.notify_all();
+    }
+}
+
+/// Slash-based paths of potential "siblings" of the `relative` file, i.e. with the same path but
+/// a different extension (in lower or upper case).
+fn siblings(relative: &str, extensions: &[&str]) -> Vec<String> {
+    let stem = match relative.rfind(['.', '/']) {
+        Some(i) if relative[i..].starts_with('.') => &relative[..i],
+        _ => relative,
+    };
+    extensions
+        .iter()
+        .flat_map(|ext| {
+            [
+                format!("{stem}.{ext}"),
+                format!("{stem}.{}", ext.to_uppercase()),
+            ]
+        })
+        .collect()
+}
+
 /// Try hard to find out some datetime info from either `exif` data, or `relative_path` of the file.
 fn try_deduce_date<'a>(
     exif: Option<&Exif>,
+    date_tags: &[DateTag],
     relative_path: &str,
-    date_paths: impl Iterator<Item = &'a config::DatePath>,
-) -> Option<NaiveDateTime> {
+    date_paths: impl Iterator<Item = &'a DatePath>,
+) -> Option<(NaiveDateTime, Option<FixedOffset>, DateSource)> {
     if let Some(exif) = exif {
-        use exif::Tag;
-        // TODO[LATER]: are ther other fields we could try?
-        if let Some(d) = vec![Tag::DateTime, Tag::DateTimeOriginal]
-            .into_iter()
-            .filter_map(|tag| exif.datetime(tag))
-            .filter_map(|dt| dt.to_naive_opt())
-            .next()
+        if let Some(d) = date_tags
+            .iter()
+            .filter_map(|&tag| exif.date_tag(tag))
+            .find_map(|dt| Some((dt.to_naive_opt()?, dt.offset_opt(), DateSource::Exif)))
         {
             return Some(d);
         }
@@ -300,22 +872,290 @@ fn try_deduce_date<'a>(
             let date = NaiveDateTime::parse_from_str(&buf, YMD_HMS)
                 .or_else(|_| NaiveDate::parse_from_str(&buf, YMD).map(|d| d.and_hms(0, 0, 0)));
             if let Ok(d) = date {
-                return Some(d);
+                return Some((d, None, DateSource::Path));
             }
         }
     }
-    // TODO[LATER]: try extracting date from file's creation and modification date (NOTE: latter can be earlier than former on Windows!)
     None
 }
 
+/// Guesses the offset of a `local` camera time from UTC, given the `utc` time from GPS. The
+/// clocks are never perfectly in sync, so the offset is rounded to a quarter of an hour.
+fn offset_from_gps(local: NaiveDateTime, utc: NaiveDateTime) -> Option<FixedOffset> {
+    const QUARTER: i64 = 15 * 60;
+    let seconds = (local - utc).num_seconds();
+    let rounded = (seconds + seconds.signum() * QUARTER / 2) / QUARTER * QUARTER;
+    // Offsets in use range from -12:00 to +14:00.
+    if !(-12 * 3600..=14 * 3600).contains(&rounded) {
+        return None;
+    }
+    FixedOffset::east_opt(rounded as i32)
+}
+
+/// Local date of the file from its filesystem timestamps. Note that modification time can be
+/// earlier than creation time, e.g. after copying the file on Windows.
+fn filesystem_date(metadata: &fs::Metadata, which: FileDate) -> Option<DateTime<Local>> {
+    let modified = metadata.modified().ok();
+    let time = match which {
+        FileDate::Modified => modified,
+        FileDate::Created => match (metadata.created().ok(), modified) {
+            (Some(created), Some(modified)) => Some(created.min(modified)),
+            (created, modified) => created.or(modified),
+        },
+    };
+    time.map(DateTime::<Local>::from)
+}
+
 #[cfg(test)]
 mod test {
+    use image::{DynamicImage, GenericImageView, RgbImage};
     use tempfile::tempdir;
 
     use crate::db;
 
     use super::*;
 
+    #[test]
+    fn siblings_by_extension() {
+        assert_eq!(
+            siblings("foo-dir/IMG_1234.CR2", &["jpg"]),
+            vec!["foo-dir/IMG_1234.jpg", "foo-dir/IMG_1234.JPG"]
+        );
+        assert_eq!(
+            siblings("foo.dir/IMG_1234", &["nef"]),
+            vec!["foo.dir/IMG_1234.nef", "foo.dir/IMG_1234.NEF"]
+        );
+    }
+
+    #[test]
+    fn filesystem_date_fallback() {
+        let root = tempdir().unwrap();
+        let path = root.path().join("foo.jpg");
+        std::fs::write(&path, "").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+
+        let modified = filesystem_date(&metadata, FileDate::Modified).unwrap();
+        let created = filesystem_date(&metadata, FileDate::Created).unwrap();
+        assert!((Local::now() - modified).num_minutes().abs() < 1);
+        assert!(created <= modified);
+    }
+
+    #[test]
+    fn offset_from_gps_time() {
+        let local = NaiveDate::from_ymd(2021, 3, 4).and_hms(17, 6, 7);
+        let at = |h, m, s| NaiveDate::from_ymd(2021, 3, 4).and_hms(h, m, s);
+        assert_eq!(
+            offset_from_gps(local, at(15, 5, 58)),
+            FixedOffset::east_opt(2 * 3600)
+        );
+        assert_eq!(
+            offset_from_gps(local, at(11, 40, 0)),
+            FixedOffset::east_opt(5 * 3600 + 30 * 60)
+        );
+        assert_eq!(
+            offset_from_gps(at(7, 0, 0), at(17, 3, 0)),
+            FixedOffset::west_opt(10 * 3600)
+        );
+        assert_eq!(offset_from_gps(at(1, 0, 0), at(17, 0, 0)), None);
+    }
+
+    #[test]
+    fn reorient_old_thumbnails() {
+        let root = tempdir().unwrap();
+        // JPEG with an Exif block holding just the orientation: "rotate 90 degrees clockwise".
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 2))
+            .write_to(&mut jpeg, image::ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let tiff: &[u8] = &[
+            0x49, 0x49, 0x2A, 0, 8, 0, 0, 0, // header
+            1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, // IFD0 with Orientation
+            0, 0, 0, 0, // no next IFD
+        ];
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend((2 + 6 + tiff.len() as u16).to_be_bytes());
+        app1.extend(b"Exif\0\0");
+        app1.extend(tiff);
+        let file = [&jpeg[..2], &app1, &jpeg[2..]].concat();
+        fs::write(root.path().join("rotated.jpg"), file).unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let info = crate::model::FileInfo {
+            thumb: jpeg,
+            ..crate::model::FileInfo::with_hash("fake-hash")
+        };
+        db::upsert(&conn, "foo-marker", "rotated.jpg", &info).unwrap();
+        db::set_marker_seen(
+            &conn,
+            "foo-marker",
+            root.path().to_str().unwrap(),
+            "marker.json",
+            None,
+        )
+        .unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        reorient_thumbnails(&db).unwrap();
+
+        let conn = db.lock().unwrap();
+        assert_eq!(db::unoriented_files(&conn).run(()).count(), 0);
+        let (_, info, _) = db::visible_files_in_limit_and_offset(&conn)
+            .run((THUMBNAIL_SIZE, 1, 0))
+            .next()
+            .unwrap()
+            .unwrap();
+        let thumb = image::load_from_memory(&info.thumb).unwrap();
+        assert_eq!(thumb.dimensions(), (2, 4));
+    }
+
+    #[test]
+    fn stage1_records_problems() {
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        fs::write(root.path().join("broken.jpg"), "not really a JPEG").unwrap();
+        let tree = Tree::open(root.path().join("marker.json"), &Config::default()).unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        stage1(0, &tree, None, &db, &InFlight::default(), OnExisting::Skip).unwrap();
+
+        let conn = db.lock().unwrap();
+        let problems = db::scan_problems(&conn)
+            .run(())
+            .map(|v| v.unwrap())
+            .map(|(marker, path, kind, _)| (marker, path, kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![(
+                "foo-marker".to_string(),
+                "broken.jpg".to_string(),
+                ProblemKind::Decode
+            )]
+        );
+        assert_eq!(db::exists(&conn, "foo-marker", "broken.jpg"), Ok(false));
+    }
+
+    #[test]
+    fn stage1_skips_small() {
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        fs::write(root.path().join("notes.jpg"), "not an image").unwrap();
+        let config = Config {
+            ignore_small: Some(IgnoreSmall {
+                w: Some(100),
+                h: Some(100),
+                bytes: Some(1000),
+            }),
+            ..Default::default()
+        };
+        let tree = Tree::open(root.path().join("marker.json"), &config).unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let db = Arc::new(Mutex::new(conn));
+
+        stage1(0, &tree, None, &db, &InFlight::default(), OnExisting::Skip).unwrap();
+
+        let conn = db.lock().unwrap();
+        assert_eq!(
+            db::skipped(&conn)
+                .run(())
+                .map(|v| v.unwrap())
+                .collect::<Vec<_>>(),
+            vec![(
+                "foo-marker".to_string(),
+                "notes.jpg".to_string(),
+                "small image: 12 bytes".to_string()
+            )]
+        );
+        assert_eq!(db::exists(&conn, "foo-marker", "notes.jpg"), Ok(false));
+        // Not decoded, or it would have failed as a problem.
+        assert_eq!(db::scan_problems(&conn).run(()).count(), 0);
+    }
+
+    #[test]
+    fn deferred_thumbnails() {
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(40, 20))
+            .write_to(&mut jpeg, image::ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        fs::write(root.path().join("photo.jpg"), jpeg).unwrap();
+        let config = Config {
+            defer_thumbnails: true,
+            ..Default::default()
+        };
+        let tree = Tree::open(root.path().join("marker.json"), &config).unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        db::set_marker_seen(
+            &conn,
+            "foo-marker",
+            root.path().to_str().unwrap(),
+            "marker.json",
+            None,
+        )
+        .unwrap();
+        let db = Arc::new(Mutex::new(conn));
+        let sources = |db: &SyncedDb| {
+            db::thumbnail_sources(&db.lock().unwrap())
+                .run(())
+                .map(|v| v.unwrap())
+                .collect::<Vec<_>>()
+        };
+        let thumb = |db: &SyncedDb| {
+            let conn = db.lock().unwrap();
+            let mut query = db::visible_files_in_limit_and_offset(&conn);
+            let file = query.run((THUMBNAIL_SIZE, 1, 0)).next().unwrap().unwrap();
+            file.1.thumb
+        };
+
+        stage1(0, &tree, None, &db, &InFlight::default(), OnExisting::Skip).unwrap();
+        assert_eq!(sources(&db), vec![(Some(ThumbnailSource::Pending), 1)]);
+        assert!(thumb(&db).is_empty());
+
+        make_pending_thumbnails(&db, &InFlight::default(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(sources(&db), vec![(Some(ThumbnailSource::Decoder), 1)]);
+        let made = thumb(&db);
+        assert_eq!(
+            image::load_from_memory(&made).unwrap().dimensions(),
+            (200, 100)
+        );
+
+        // Refreshing the file doesn't lose the thumbnail.
+        stage1(
+            0,
+            &tree,
+            None,
+            &db,
+            &InFlight::default(),
+            OnExisting::Refresh,
+        )
+        .unwrap();
+        assert_eq!(sources(&db), vec![(Some(ThumbnailSource::Decoder), 1)]);
+        assert_eq!(thumb(&db), made);
+    }
+
+    #[test]
+    fn bytes_in_flight() {
+        let in_flight = InFlight::new(Some(100));
+        let first = in_flight.acquire(60);
+        std::thread::scope(|s| {
+            let second = s.spawn(|| drop(in_flight.acquire(60)));
+            // The second file waits until the first one is done with.
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!second.is_finished());
+            drop(first);
+            second.join().unwrap();
+        });
+        // A file bigger than the limit still gets through on its own.
+        drop(in_flight.acquire(500));
+        assert_eq!(*in_flight.used.lock().unwrap(), 0);
+    }
+
     #[test]
     fn stage2_file_not_found() {
         // arrange
@@ -329,11 +1169,7 @@ mod test {
             .unwrap();
         // writeln!(marker_file, r#"{"id": "foo-marker"}"#).unwrap();
         drop(marker_file);
-        let tree = Tree::open(
-            root.path().join("marker.json"),
-            &config::DatePathsPerMarker::new(),
-        )
-        .unwrap();
+        let tree = Tree::open(root.path().join("marker.json"), &Config::default()).unwrap();
 
         let conn = rusqlite::Connection::open_in_memory().unwrap();
         let db = Arc::new(Mutex::new(conn));
@@ -346,7 +1182,10 @@ mod test {
             &crate::model::FileInfo {
                 hash: hash(&Vec::new()),
                 date: None,
+                date_offset: None,
+                date_source: None,
                 thumb: Vec::new(),
+                media: crate::model::MediaType::Image,
             },
         )
         .unwrap();
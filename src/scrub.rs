@@ -0,0 +1,272 @@
+//! Scrubbing: verifying that files of a tree still match their digests, to find bit rot or
+//! tampering early. Unlike `stage2` of scanning, it can be limited to a part of the tree and
+//! throttled, so that it can run in the background, and it resumes where it was interrupted.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{NaiveDateTime, Utc};
+use path_slash::PathBufExt;
+
+use crate::db::{self, SyncedDb};
+use crate::hashing;
+use crate::interlude::*;
+use crate::scanning::Tree;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScrubOptions {
+    /// Percentage of locations of the tree to verify, least recently verified first.
+    pub percent: u64,
+    /// Limit of bytes read per second; unlimited if not set.
+    pub bytes_per_sec: Option<u64>,
+}
+
+impl Default for ScrubOptions {
+    fn default() -> Self {
+        Self {
+            percent: 100,
+            bytes_per_sec: None,
+        }
+    }
+}
+
+/// Counts of locations processed by [scrub].
+#[derive(Debug, Default, PartialEq)]
+pub struct Scrubbed {
+    /// Locations with contents matching the digest of their file.
+    pub verified: usize,
+    /// Locations with contents not matching, recorded in the `scrub_mismatch` table.
+    pub mismatched: usize,
+    /// Locations without a file anymore; they're removed from the catalog by scanning.
+    pub missing: usize,
+    /// Locations which could not be read, e.g. for lack of permissions.
+    pub unreadable: usize,
+    pub bytes: u64,
+}
+
+/// Re-reads and re-hashes locations of the `tree`, and records when each was verified. A scrub
+/// which was interrupted is resumed: it keeps the number of locations to verify which it was
+/// started with, and locations it already got to are not read again. Other `options`, like the
+/// throttle, are taken from the resuming call.
+pub fn scrub(db: &SyncedDb, tree: &Tree, options: ScrubOptions) -> Result<Scrubbed> {
+    let (started, target) = {
+        let db = db.lock().unwrap();
+        match db::scrub_run(&db, &tree.marker)? {
+            Some((started, target)) => {
+                iprintln!("Resuming scrub of " tree.marker;? " started at " started);
+                (started, target)
+            }
+            None => {
+                let started = now();
+                let n = db::n_scrubbable(&db, &tree.marker)?;
+                let target = (n * options.percent.min(100)).div_ceil(100);
+                db::start_scrub(&db, &tree.marker, started, target)?;
+                (started, target)
+            }
+        }
+    };
+    let locations = {
+        let db = db.lock().unwrap();
+        let done = db::n_scrubbed_since(&db, &tree.marker, started)?;
+        let limit = target.saturating_sub(done);
+        db::least_recently_scrubbed(&db, &tree.marker, started, limit)?
+    };
+
+    let mut scrubbed = Scrubbed::default();
+    let mut throttle = Throttle::new(options.bytes_per_sec);
+    for (relative, hash) in locations {
+        let path = tree.root.join(PathBuf::from_slash(&relative));
+        let (algorithm, expected) = hashing::strongest_digest(&db.lock().unwrap(), &hash)?;
+        let digests = fs::File::open(&path).and_then(|file| {
+            let reader = Throttled {
+                inner: file,
+                throttle: &mut throttle,
+            };
+            hashing::digests_of_reader(reader, &[algorithm])
+        });
+        let actual = match digests {
+            Ok(mut digests) => digests.remove(0).1,
+            Err(err) => {
+                if err.kind() == io::ErrorKind::NotFound {
+                    scrubbed.missing += 1;
+                } else {
+                    ieprintln!("\nFailed to read file " &path;? ", skipping: " err);
+                    scrubbed.unreadable += 1;
+                }
+                // Recorded as attempted, so that the next scrubs get to the rest of the tree first.
+                db::set_scrubbed(&db.lock().unwrap(), &tree.marker, &relative, now())?;
+                continue;
+            }
+        };
+
+        let db = db.lock().unwrap();
+        let verified = now();
+        if actual == expected {
+            db::clear_scrub_mismatch(&db, &tree.marker, &relative)?;
+            scrubbed.verified += 1;
+            print!(",");
+        } else {
+            iprintln!("\nBAD HASH: " actual " != " expected " (" algorithm;? ") @ " path;?);
+            db::add_scrub_mismatch(
+                &db,
+                &tree.marker,
+                &relative,
+                algorithm,
+                &expected,
+                &actual,
+                verified,
+            )?;
+            scrubbed.mismatched += 1;
+        }
+        db::set_verified(&db, &tree.marker, &relative, verified)?;
+        io::stdout().flush()?;
+    }
+    scrubbed.bytes = throttle.bytes;
+
+    db::finish_scrub(&db.lock().unwrap(), &tree.marker)?;
+    Ok(scrubbed)
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+/// Keeps the average rate of reading under a limit, by sleeping whenever it's ahead of it.
+struct Throttle {
+    bytes_per_sec: Option<u64>,
+    start: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            bytes_per_sec,
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        let Some(limit) = self.bytes_per_sec else {
+            return;
+        };
+        let due = Duration::from_secs_f64(self.bytes as f64 / limit as f64);
+        if let Some(ahead) = due.checked_sub(self.start.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+struct Throttled<'a, R> {
+    inner: R,
+    throttle: &'a mut Throttle,
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.throttle.consume(n);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::model::FileInfo;
+    use crate::scanning::hash;
+
+    #[test]
+    fn scrub_part_and_resume() {
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        for name in ["a.jpg", "b.jpg", "c.jpg"] {
+            fs::write(root.path().join(name), name).unwrap();
+        }
+        let tree = Tree::open(root.path().join("marker.json"), &Config::default()).unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        for (name, contents) in [("a.jpg", "a.jpg"), ("b.jpg", "rotten"), ("c.jpg", "c.jpg")] {
+            let info = FileInfo::with_hash(&hash(contents.as_bytes()));
+            db::upsert(&conn, "foo-marker", name, &info).unwrap();
+        }
+        let db = Arc::new(Mutex::new(conn));
+        let mismatches = |db: &SyncedDb| {
+            db::scrub_mismatches(&db.lock().unwrap())
+                .run(())
+                .map(|v| v.unwrap().1)
+                .collect::<Vec<_>>()
+        };
+
+        // Two thirds of the tree, never verified ones first.
+        let options = ScrubOptions {
+            percent: 60,
+            ..Default::default()
+        };
+        let scrubbed = scrub(&db, &tree, options).unwrap();
+        assert_eq!((scrubbed.verified, scrubbed.mismatched), (1, 1));
+        assert_eq!(scrubbed.bytes, 10);
+        assert_eq!(mismatches(&db), vec!["b.jpg"]);
+
+        // An interrupted scrub of the whole tree only reads the rest.
+        let started = now();
+        db::start_scrub(&db.lock().unwrap(), "foo-marker", started, 3).unwrap();
+        db::set_verified(&db.lock().unwrap(), "foo-marker", "c.jpg", now()).unwrap();
+        let scrubbed = scrub(&db, &tree, ScrubOptions::default()).unwrap();
+        assert_eq!((scrubbed.verified, scrubbed.mismatched), (1, 1));
+        assert_eq!(
+            db::scrub_run(&db.lock().unwrap(), "foo-marker").unwrap(),
+            None
+        );
+
+        // A fixed file is no longer reported.
+        fs::write(root.path().join("b.jpg"), "rotten").unwrap();
+        let scrubbed = scrub(&db, &tree, ScrubOptions::default()).unwrap();
+        assert_eq!((scrubbed.verified, scrubbed.mismatched), (3, 0));
+        assert!(mismatches(&db).is_empty());
+    }
+
+    #[test]
+    fn scrub_past_missing() {
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("marker.json"), r#"{"id": "foo-marker"}"#).unwrap();
+        fs::write(root.path().join("b.jpg"), "b.jpg").unwrap();
+        let tree = Tree::open(root.path().join("marker.json"), &Config::default()).unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        for name in ["a.jpg", "b.jpg"] {
+            let info = FileInfo::with_hash(&hash(name.as_bytes()));
+            db::upsert(&conn, "foo-marker", name, &info).unwrap();
+        }
+        let db = Arc::new(Mutex::new(conn));
+        let options = ScrubOptions {
+            percent: 50,
+            ..Default::default()
+        };
+
+        let scrubbed = scrub(&db, &tree, options).unwrap();
+        assert_eq!((scrubbed.verified, scrubbed.missing), (0, 1));
+        // The missing file doesn't take the place of the never scrubbed one.
+        let scrubbed = scrub(&db, &tree, options).unwrap();
+        assert_eq!((scrubbed.verified, scrubbed.missing), (1, 0));
+    }
+
+    #[test]
+    fn throttled_reading() {
+        let mut throttle = Throttle::new(Some(100_000));
+        let mut reader = Throttled {
+            inner: &[0u8; 20_000][..],
+            throttle: &mut throttle,
+        };
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        assert!(throttle.start.elapsed() >= Duration::from_millis(200));
+    }
+}
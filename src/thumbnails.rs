@@ -0,0 +1,244 @@
+//! Thumbnails of files in a few sizes (see: [SIZES]). The ones of [THUMBNAIL_SIZE] are made when
+//! scanning, others on demand, e.g. when the gallery is zoomed.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use exif::Reader as ExifReader;
+use image::imageops::FilterType;
+use path_slash::PathBufExt;
+
+use crate::db::{self, SyncedDb};
+use crate::imaging::{ExifExt, Orientation, THUMBNAIL_SIZE};
+use crate::interlude::*;
+use crate::model::MediaType;
+use crate::scanning::{encode_thumbnail, has_extension, image_data, RAW_EXTENSIONS};
+
+/// Sizes of thumbnails, in pixels of the side of the square they fit in.
+pub const SIZES: [u32; 3] = [100, THUMBNAIL_SIZE, 400];
+
+/// The smallest size of thumbnails which fills a tile of `tile` pixels, or the biggest one.
+pub fn size_for_tile(tile: f32) -> u32 {
+    SIZES
+        .into_iter()
+        .find(|&size| size as f32 >= tile)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+/// Makes the thumbnail of the file of `size`, and stores it. It's made from the best available
+/// location: an original photo, else the preview embedded in a RAW file, and as the last resort
+/// by scaling down a bigger thumbnail. Returns false if none is available, e.g. because no tree
+/// with the file is mounted.
+pub fn make(db: &SyncedDb, hash: &str, size: u32) -> Result<bool> {
+    let locations = db::thumbnail_locations(&db.lock().unwrap(), hash)?;
+    // Videos are not decoded, so they only get their placeholder scaled.
+    let (raws, originals): (Vec<_>, Vec<_>) = locations
+        .into_iter()
+        .filter(|(_, _, media)| *media == MediaType::Image)
+        .partition(|(_, relative, _)| has_extension(relative, &RAW_EXTENSIONS));
+    for (root, relative, _) in originals.into_iter().chain(raws) {
+        let path = Path::new(&root).join(PathBuf::from_slash(&relative));
+        let Ok(buf) = fs::read(&path) else {
+            continue;
+        };
+        let exif = ExifReader::new()
+            .read_from_container(&mut io::Cursor::new(&buf))
+            .ok();
+        let is_raw = has_extension(&relative, &RAW_EXTENSIONS);
+        let Some(image_buf) = image_data(&buf, exif.as_ref(), is_raw) else {
+            continue;
+        };
+        let orientation = exif
+            .as_ref()
+            .and_then(|e| e.orientation())
+            .unwrap_or(Orientation::Normal);
+        let Ok(thumb) = encode_thumbnail(image_buf, exif.as_ref(), orientation, size) else {
+            continue;
+        };
+        db::set_thumbnail(&db.lock().unwrap(), hash, size, &thumb.jpeg)?;
+        return Ok(true);
+    }
+
+    let largest = db::largest_thumbnail(&db.lock().unwrap(), hash)?;
+    let Some((_, jpeg)) = largest.filter(|(largest_size, _)| *largest_size > size) else {
+        return Ok(false);
+    };
+    let img = image::load_from_memory(&jpeg)?.resize(size, size, FilterType::CatmullRom);
+    let mut jpeg = Vec::new();
+    img.write_to(&mut jpeg, image::ImageOutputFormat::Jpeg(90))?;
+    db::set_thumbnail(&db.lock().unwrap(), hash, size, &jpeg)?;
+    Ok(true)
+}
+
+/// How long a thumbnail which couldn't be made is not tried again, as the gallery keeps
+/// requesting it on every redraw.
+const RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Makes thumbnails requested e.g. by the gallery in a background thread, so that it's not
+/// blocked meanwhile; they're shown when it's redrawn. A thumbnail which couldn't be made, e.g.
+/// as no tree with the file was mounted, is tried again only after [RETRY_AFTER]. If making one
+/// panics, e.g. on a corrupt file, it's not retried, and the thread goes on with the others.
+#[derive(Clone)]
+pub struct Maker {
+    requests: mpsc::Sender<(String, u32)>,
+    requested: Arc<Mutex<HashSet<(String, u32)>>>,
+    /// Requests which failed, with the time they can be retried at.
+    failed: Arc<Mutex<HashMap<(String, u32), Instant>>>,
+}
+
+impl Maker {
+    pub fn spawn(db: SyncedDb) -> Self {
+        let (requests, received) = mpsc::channel::<(String, u32)>();
+        let requested: Arc<Mutex<HashSet<(String, u32)>>> = Default::default();
+        let failed: Arc<Mutex<HashMap<(String, u32), Instant>>> = Default::default();
+        let (done, retries) = (requested.clone(), failed.clone());
+        std::thread::spawn(move || {
+            for (hash, size) in received {
+                let made = panic::catch_unwind(AssertUnwindSafe(|| make(&db, &hash, size)));
+                let retry = match made {
+                    Ok(Ok(made)) => !made,
+                    Ok(Err(err)) => {
+                        ieprintln!("Failed to make thumbnail of " hash ": " error_chain(&err));
+                        true
+                    }
+                    Err(_) => {
+                        ieprintln!("Panicked while making thumbnail of " hash ", not retrying");
+                        false
+                    }
+                };
+                if retry {
+                    let request = (hash, size);
+                    let mut retries = retries.lock().unwrap();
+                    retries.insert(request.clone(), Instant::now() + RETRY_AFTER);
+                    done.lock().unwrap().remove(&request);
+                }
+            }
+        });
+        Self {
+            requests,
+            requested,
+            failed,
+        }
+    }
+
+    /// Asks for the thumbnail of the file of `size` to be made, unless it already was, or it
+    /// recently failed.
+    pub fn request(&self, hash: &str, size: u32) {
+        let request = (hash.to_string(), size);
+        {
+            let mut failed = self.failed.lock().unwrap();
+            match failed.get(&request) {
+                Some(&retry_at) if retry_at > Instant::now() => return,
+                Some(_) => {
+                    failed.remove(&request);
+                }
+                None => {}
+            }
+        }
+        if self.requested.lock().unwrap().insert(request.clone())
+            && self.requests.send(request).is_err()
+        {
+            ieprintln!("Thumbnail maker stopped, can't make thumbnail of " hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, GenericImageView, RgbImage};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::marker;
+    use crate::model::FileInfo;
+
+    #[test]
+    fn sizes_for_tiles() {
+        assert_eq!(size_for_tile(64.0), 100);
+        assert_eq!(size_for_tile(100.0), 100);
+        assert_eq!(size_for_tile(150.0), 200);
+        assert_eq!(size_for_tile(1000.0), 400);
+    }
+
+    #[test]
+    fn maker_retries_unavailable() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let maker = Maker::spawn(Arc::new(Mutex::new(conn)));
+        let request = ("unmounted-hash".to_string(), 400);
+        let failed_after = |since: Instant| {
+            let start = Instant::now();
+            while maker.failed.lock().unwrap().get(&request) <= Some(&since) {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        let since = Instant::now();
+        maker.request(&request.0, request.1);
+        failed_after(since);
+
+        // Not tried again on every redraw of the gallery...
+        maker.request(&request.0, request.1);
+        assert!(maker.requested.lock().unwrap().is_empty());
+        // ...but once it's time, e.g. after the disk may have been attached.
+        let since = Instant::now();
+        maker.failed.lock().unwrap().insert(request.clone(), since);
+        maker.request(&request.0, request.1);
+        failed_after(since);
+    }
+
+    #[test]
+    fn make_from_best_location() {
+        let root = tempdir().unwrap();
+        let mut photo = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(800, 400))
+            .write_to(&mut photo, image::ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        fs::write(root.path().join("photo.jpg"), &photo).unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        db::init(&conn).unwrap();
+        let mut thumb = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(200, 100))
+            .write_to(&mut thumb, image::ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let info = FileInfo {
+            thumb,
+            ..FileInfo::with_hash("photo-hash")
+        };
+        db::upsert(&conn, "foo-marker", "photo.jpg", &info).unwrap();
+        db::upsert(&conn, "foo-marker", "missing.jpg", &info).unwrap();
+        db::set_marker_seen(
+            &conn,
+            "foo-marker",
+            root.path().to_str().unwrap(),
+            marker::FILE_NAME,
+            None,
+        )
+        .unwrap();
+        let db = Arc::new(Mutex::new(conn));
+        let size_of = |size| {
+            let conn = db.lock().unwrap();
+            let mut query = db::visible_files_in_limit_and_offset(&conn);
+            let (_, file, thumb_size) = query.run((size, 1, 0)).next().unwrap().unwrap();
+            let img = image::load_from_memory(&file.thumb).unwrap();
+            (thumb_size, img.dimensions())
+        };
+
+        // Until made, the nearest bigger thumbnail is used.
+        assert_eq!(size_of(100), (Some(200), (200, 100)));
+        assert!(make(&db, "photo-hash", 400).unwrap());
+        assert_eq!(size_of(400), (Some(400), (400, 200)));
+
+        // Without the original, the thumbnail is scaled from the biggest one.
+        fs::remove_file(root.path().join("photo.jpg")).unwrap();
+        assert!(make(&db, "photo-hash", 100).unwrap());
+        assert_eq!(size_of(100), (Some(100), (100, 50)));
+        assert!(!make(&db, "photo-hash", 800).unwrap());
+    }
+}
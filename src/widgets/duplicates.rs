@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use iced::widget::{button, image, scrollable, text, Column, Row};
+use iced::{theme, Element, Length};
+
+use crate::db::{self, Rowid, SqlValue};
+use crate::duplicates;
+use crate::imaging::THUMBNAIL_SIZE;
+use crate::model::FileInfo;
+use crate::widgets::gallery::Selection;
+
+/// Groups of near-duplicate files shown in the gallery, each group in a row.
+pub struct Panel {
+    groups: Vec<Vec<(Rowid, FileInfo)>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Select(Rowid),
+}
+
+impl Panel {
+    pub fn load(db: &rusqlite::Connection) -> Self {
+        let hashes = db::visible_perceptual_hashes(db)
+            .run(())
+            .map(|v| v.unwrap())
+            .filter_map(|(rowid, phash)| Some((rowid, phash?)))
+            .collect::<Vec<_>>();
+        let clusters = duplicates::clusters(&hashes, duplicates::MAX_DISTANCE);
+
+        let rowids = clusters
+            .iter()
+            .flatten()
+            .copied()
+            .map(SqlValue::from)
+            .collect::<Vec<_>>();
+        let mut files = db::files_by_rowids(db)
+            .run((THUMBNAIL_SIZE, std::rc::Rc::new(rowids)))
+            .map(|v| v.unwrap())
+            .collect::<HashMap<_, _>>();
+        let groups = clusters
+            .into_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .filter_map(|rowid| Some((rowid, files.remove(&rowid)?)))
+                    .collect()
+            })
+            .collect();
+        Self { groups }
+    }
+
+    pub fn view(&self, selection: &Selection) -> Element<'_, Event> {
+        if self.groups.is_empty() {
+            return text("No near-duplicates found.").into();
+        }
+        let groups = self.groups.iter().map(|group| {
+            let tiles = group
+                .iter()
+                .map(|(rowid, file)| tile(*rowid, file, selection.rowids.contains(rowid)));
+            Row::with_children(tiles.collect()).spacing(10).into()
+        });
+        let groups = Column::with_children(groups.collect())
+            .spacing(25)
+            .padding(25);
+        scrollable(groups).width(Length::Fill).into()
+    }
+}
+
+/// Thumbnail of the file with its date, which selects the file when clicked.
+fn tile(rowid: Rowid, file: &FileInfo, selected: bool) -> Element<'_, Event> {
+    let date = file.date.map(|d| d.to_string()).unwrap_or_default();
+    let content = Column::new()
+        .spacing(5)
+        .push(
+            image(image::Handle::from_memory(file.thumb.clone()))
+                .width(Length::Fixed(200.0))
+                .height(Length::Fixed(200.0)),
+        )
+        .push(text(date).size(14));
+    let style = if selected {
+        theme::Button::Primary
+    } else {
+        theme::Button::Text
+    };
+    button(content)
+        .style(style)
+        .on_press(Event::Select(rowid))
+        .into()
+}
@@ -13,6 +13,8 @@ use tracing::{span, Level};
 
 use crate::db;
 use crate::interlude::*;
+use crate::model::MediaType;
+use crate::thumbnails;
 
 pub struct Gallery<Message> {
     pub db: Arc<Mutex<rusqlite::Connection>>,
@@ -22,6 +24,7 @@ pub struct Gallery<Message> {
     tile_h: f32,
     spacing: f32,
     on_select: Option<Box<dyn Fn(Selection) -> Message>>,
+    thumbnail_maker: Option<thumbnails::Maker>,
 }
 
 #[derive(Clone, Default, Debug)]
@@ -77,9 +80,22 @@ impl<Message> Gallery<Message> {
             tile_h: 200.0,
             spacing: 25.0,
             on_select: None,
+            thumbnail_maker: None,
         }
     }
 
+    pub fn tile_size(mut self, size: f32) -> Self {
+        self.tile_w = size;
+        self.tile_h = size;
+        self
+    }
+
+    /// Thumbnails of the size matching the tiles, if missing, are requested from the `maker`.
+    pub fn thumbnail_maker(mut self, maker: thumbnails::Maker) -> Self {
+        self.thumbnail_maker = Some(maker);
+        self
+    }
+
     pub fn with_selection(mut self, s: Selection) -> Self {
         self.selection = s;
         self
@@ -214,8 +230,9 @@ where
         // TODO[LATER]: think whether to remove .unwrap()
         let span_filequery_init = span!(Level::TRACE, "draw/filequery_init");
         let guard_filequery_init = span_filequery_init.enter();
+        let thumb_size = thumbnails::size_for_tile(self.tile_w.max(self.tile_h));
         let mut query = crate::db::visible_files_in_limit_and_offset(&db);
-        let file_iter = query.run((limit.into(), offset.into())).map(|v| v.unwrap());
+        let file_iter = query.run((thumb_size, limit.into(), offset.into())).map(|v| v.unwrap());
         drop(guard_filequery_init);
 
         // println!("{:?} {:?}", layout.bounds(), &viewport);
@@ -225,10 +242,17 @@ where
         let mut last_date = String::new();
         let mut x = self.spacing;
         let mut y = self.spacing + (offset / columns) as f32 * (self.tile_h + self.spacing);
-        for (rowid, file) in file_iter {
+        for (rowid, file, file_thumb_size) in file_iter {
             let span_fileiter = span!(Level::TRACE, "draw/fileiter");
             let _guard_fileiter = span_fileiter.enter();
 
+            // Until made, the thumbnail of the nearest size gets scaled.
+            if file_thumb_size != Some(thumb_size) {
+                if let Some(maker) = &self.thumbnail_maker {
+                    maker.request(&file.hash, thumb_size);
+                }
+            }
+
             // Mark tile as selected when appropriate.
             // FIXME: O(n)!!!
             if self.selection.rowids.contains(&rowid) {
@@ -251,31 +275,99 @@ where
             // Extract dimensions of thumbnail
             let span_jpegdec = span!(Level::TRACE, "draw/jpegdec");
             let guard_jpegdec = span_jpegdec.enter();
-            let (w, h) = image::jpeg::JpegDecoder::new(std::io::Cursor::new(&file.thumb))
-                .unwrap()
-                .dimensions();
+            // Files with pending thumbnails (see: scanning::make_pending_thumbnails) have an empty
+            // one, and get a placeholder filling the tile.
+            let pending = file.thumb.is_empty();
+            let (w, h) = if pending {
+                (self.tile_w as u32, self.tile_h as u32)
+            } else {
+                image::jpeg::JpegDecoder::new(std::io::Cursor::new(&file.thumb))
+                    .unwrap()
+                    .dimensions()
+            };
             drop(guard_jpegdec);
             let (w, h) = (w as f32, h as f32);
-            // Calculate scale, keeping aspect ratio
-            let scale = 1_f32.min((w / self.tile_w).max(h / self.tile_h));
+            // Scale the thumbnail to fit the tile, keeping aspect ratio
+            let scale = (w / self.tile_w).max(h / self.tile_h);
+            let (w, h) = (w / scale, h / scale);
             // Calculate alignment so that the thumbnail is centered in its space
-            let align_x = (self.tile_w - w / scale) / 2.0;
-            let align_y = (self.tile_h - h / scale) / 2.0;
+            let align_x = (self.tile_w - w) / 2.0;
+            let align_y = (self.tile_h - h) / 2.0;
 
             let span_imagethumb = span!(Level::TRACE, "draw/imagethumb");
             let guard_imagethumb = span_imagethumb.enter();
-            renderer.draw(
-                iced_image::Handle::from_memory(file.thumb),
-                Rectangle {
-                    x: x + align_x,
-                    y: y + align_y,
-                    width: w,
-                    height: h,
-                },
-            );
+            let bounds = Rectangle {
+                x: x + align_x,
+                y: y + align_y,
+                width: w,
+                height: h,
+            };
+            if pending {
+                renderer.fill_quad(
+                    Quad {
+                        bounds,
+                        border_radius: 0.0.into(),
+                        border_width: 0.,
+                        border_color: Color::WHITE,
+                    },
+                    Color::from_rgb(0.85, 0.85, 0.85),
+                );
+                renderer.fill_text(Text {
+                    content: "...",
+                    bounds: Rectangle {
+                        x: bounds.center_x(),
+                        y: bounds.center_y(),
+                        ..bounds
+                    },
+                    size: 20.0,
+                    line_height: Default::default(),
+                    color: Color::from_rgb(0.5, 0.5, 0.5),
+                    font: Font::DEFAULT,
+                    horizontal_alignment: alignment::Horizontal::Center,
+                    vertical_alignment: alignment::Vertical::Center,
+                    shaping: Default::default(),
+                });
+            } else {
+                renderer.draw(iced_image::Handle::from_memory(file.thumb), bounds);
+            }
             drop(guard_imagethumb);
 
-            // Display date header if necessary
+            // Badge videos, as their thumbnail is just a placeholder.
+            if file.media == MediaType::Video {
+                let badge = Rectangle {
+                    x: x + align_x + 5.0,
+                    y: y + align_y + 5.0,
+                    width: 50.0,
+                    height: 18.0,
+                };
+                renderer.fill_quad(
+                    Quad {
+                        bounds: badge,
+                        border_radius: 3.0.into(),
+                        border_width: 0.,
+                        border_color: Color::WHITE,
+                    },
+                    Color::from_rgb(0.8, 0.2, 0.2),
+                );
+                renderer.fill_text(Text {
+                    content: "VIDEO",
+                    bounds: Rectangle {
+                        x: badge.center_x(),
+                        y: badge.center_y(),
+                        ..badge
+                    },
+                    size: 14.0,
+                    line_height: Default::default(),
+                    color: Color::WHITE,
+                    font: Font::DEFAULT,
+                    horizontal_alignment: alignment::Horizontal::Center,
+                    vertical_alignment: alignment::Vertical::Center,
+                    shaping: Default::default(),
+                });
+            }
+
+            // Display date header if necessary; files are ordered by UTC instant, but shown with
+            // the local date of where they were taken.
             // TODO[LATER]: start 1 row earlier to make sure date is not displayed too greedily
             let date = match file.date {
                 Some(d) => d.format("%Y-%m-%d").to_string(),
@@ -329,6 +421,17 @@ where
                 .map(|v| v.unwrap())
                 .map(|(backend, path)| backend + ": " + path.as_str())
                 .join("\n");
+            let mut gps_query = crate::db::gps_of_file_at_offset(&db);
+            let gps = gps_query
+                .run((hovered_offset.into(),))
+                .map(|v| v.unwrap())
+                .map(|(lat, lon, alt)| match alt {
+                    Some(alt) => format!("\nGPS: {lat:.5}, {lon:.5}, {alt:.0} m"),
+                    None => format!("\nGPS: {lat:.5}, {lon:.5}"),
+                })
+                .next()
+                .unwrap_or_default();
+            let locations = locations + &gps;
             drop(guard_locations);
             let text = {
                 let content = locations.as_str();
@@ -1,2 +1,4 @@
+pub mod duplicates;
 pub mod gallery;
+pub mod problems;
 pub mod tags;
@@ -0,0 +1,35 @@
+use iced::widget::{scrollable, text, Column, Row};
+use iced::{Element, Length};
+
+use crate::db;
+use crate::model::ProblemKind;
+
+/// Files which could not be cataloged during latest scans.
+pub struct Panel {
+    problems: Vec<(String, String, ProblemKind, String)>,
+}
+
+impl Panel {
+    pub fn load(db: &rusqlite::Connection) -> Self {
+        let problems = db::scan_problems(db).run(()).map(|v| v.unwrap()).collect();
+        Self { problems }
+    }
+
+    pub fn view<Message: 'static>(&self) -> Element<'_, Message> {
+        if self.problems.is_empty() {
+            return text("No problems found in latest scans.").into();
+        }
+        let rows = self.problems.iter().map(|(marker, path, kind, message)| {
+            Row::new()
+                .spacing(20)
+                .push(text(format!("{marker}: {path}")).width(Length::FillPortion(3)))
+                .push(text(format!("{kind:?}")).width(Length::FillPortion(1)))
+                .push(text(message).width(Length::FillPortion(3)))
+                .into()
+        });
+        let rows = Column::with_children(rows.collect())
+            .spacing(10)
+            .padding(25);
+        scrollable(rows).width(Length::Fill).into()
+    }
+}